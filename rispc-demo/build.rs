@@ -3,5 +3,5 @@ extern crate rispc;
 fn main() {
   rispc::Config::new()
     .file("src/mandel.ispc")
-    .compile("libmandel.a");
+    .compile("mandel");
 }
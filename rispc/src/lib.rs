@@ -28,7 +28,7 @@
 //! extern crate rispc;
 //!
 //! fn main() {
-//!   rispc::compile_library("libmandelbrot.a", &[ "src/mandelbrot.ispc" ]);
+//!   rispc::compile_library("mandelbrot", &[ "src/mandelbrot.ispc" ]);
 //! }
 //! ```
 //!
@@ -45,7 +45,7 @@
 //!     .math_lib(rispc::Math::Fast)
 //!     .enable_fast_math(true)
 //!     .addressing(rispc::Addr::A64)
-//!     .compile("libmandelbrot.a");
+//!     .compile("mandelbrot");
 //! }
 //! ```
 //!
@@ -55,19 +55,31 @@
 #![deny(missing_docs)]
 
 extern crate bindgen;
-extern crate gcc;
+extern crate cc;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
-use std::{cmp, fs, io};
-use std::ffi::OsString;
-use std::io::{Write, BufRead};
-use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::{cmp, fmt, fs, io};
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write, BufRead, IsTerminal};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
+/// A command line about to run ispc on a single source file. See
+/// `Config::before_compile`.
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
-struct Tool {
+pub struct Tool {
   path: PathBuf,
   args: Vec<OsString>,
   envs: Vec<(OsString, OsString)>,
+  cwd: Option<PathBuf>,
 }
 
 impl Tool {
@@ -76,29 +88,221 @@ impl Tool {
       path: path,
       args: vec![],
       envs: vec![],
+      cwd: None,
     }
   }
 
-  fn arg(&mut self, s: &str) -> &mut Self {
-    self.args.push(s.into());
+  /// Adds an argument.
+  pub fn arg<S: AsRef<OsStr>>(&mut self, s: S) -> &mut Self {
+    self.args.push(s.as_ref().to_owned());
     self
   }
 
+  /// Sets an environment variable for the command.
+  pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, k: K, v: V) -> &mut Self {
+    self.envs.push((k.as_ref().to_owned(), v.as_ref().to_owned()));
+    self
+  }
+
+  /// The program run, ispc itself or a wrapper around it.
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// The arguments so far.
+  pub fn args(&self) -> &[OsString] {
+    &self.args
+  }
+
+  // What is passed to ispc's `--target`, if anything.
+  fn targets(&self) -> &str {
+    self.args.iter()
+      .filter_map(|a| a.to_str().and_then(|a| a.strip_prefix("--target=")))
+      .next()
+      .unwrap_or("")
+  }
+
   fn to_command(&self) -> Command {
     let mut cmd = Command::new(&self.path);
     cmd.args(&self.args);
     for &(ref k, ref v) in self.envs.iter() {
       cmd.env(k, v);
     }
+    if let Some(ref dir) = self.cwd {
+      cmd.current_dir(dir);
+    }
     cmd
   }
 }
 
-/// An addressing scheme. By default, `ispc` uses 32-bit addressing. If your
-/// Arrays grow to more than `2^32` elements, this will need to be changed to
-/// 64-bit.
+// A lazily computed value, such as the result of probing the compiler. It is
+// not part of the identity of whatever holds it.
+struct Cache<T>(Mutex<Option<T>>);
+
+impl<T: Clone> Cache<T> {
+  fn new() -> Cache<T> {
+    Cache(Mutex::new(None))
+  }
+
+  fn get<F: FnOnce() -> T>(&self, f: F) -> T {
+    if let Some(ref v) = *self.0.lock().unwrap() { return v.clone(); }
+    let v = f();
+    *self.0.lock().unwrap() = Some(v.clone());
+    v
+  }
+
+  // Like `get`, remembering only success.
+  fn try_get<E, F: FnOnce() -> Result<T, E>>(&self, f: F) -> Result<T, E> {
+    if let Some(ref v) = *self.0.lock().unwrap() { return Ok(v.clone()); }
+    let v = f()?;
+    *self.0.lock().unwrap() = Some(v.clone());
+    Ok(v)
+  }
+}
+
+impl<T> PartialEq for Cache<T> {
+  fn eq(&self, _: &Cache<T>) -> bool { true }
+}
+
+impl<T> Eq for Cache<T> {}
+
+impl<T> Hash for Cache<T> {
+  fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+// Copies start out empty, since what was probed may no longer hold once the
+// copy is reconfigured.
+impl<T: Clone> Clone for Cache<T> {
+  fn clone(&self) -> Cache<T> {
+    Cache::new()
+  }
+}
+
+impl<T: Clone> Default for Cache<T> {
+  fn default() -> Cache<T> {
+    Cache::new()
+  }
+}
+
+impl<T> fmt::Debug for Cache<T> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("Cache")
+  }
+}
+
+// The `CacheBackend` set with `Config::cache_backend`. Like `Cache`, it is not
+// part of the identity of the `Config`.
+#[derive(Clone, Default)]
+struct Backend(Option<Arc<dyn CacheBackend>>);
+
+impl PartialEq for Backend {
+  fn eq(&self, _: &Backend) -> bool { true }
+}
+
+impl Eq for Backend {}
+
+impl Hash for Backend {
+  fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+impl fmt::Debug for Backend {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("Backend")
+  }
+}
+
+// The functions added with `Config::post_process`, `before_compile`,
+// `after_compile` or `variant`, which, like `Backend`, are not part of the
+// identity of the `Config`.
+struct Callbacks<F: ?Sized>(Vec<Arc<F>>);
+
+type PostProcessFn = dyn Fn(&[PathBuf]) -> io::Result<()> + Send + Sync;
+type BeforeCompileFn = dyn Fn(&Path, &mut Tool) + Send + Sync;
+type AfterCompileFn = dyn Fn(&Path, &CompiledObject) + Send + Sync;
+type VariantFn = dyn Fn(&mut Config) + Send + Sync;
+// The name and contents of a member of an archive.
+type Member = (String, Vec<u8>);
+// The suffix of a specialization, its definitions, and the one target it's for,
+// if any.
+type Specialization = (String, Vec<(String, String)>, Option<Target>);
+
+impl<F: ?Sized> Clone for Callbacks<F> {
+  fn clone(&self) -> Callbacks<F> {
+    Callbacks(self.0.clone())
+  }
+}
+
+impl<F: ?Sized> Default for Callbacks<F> {
+  fn default() -> Callbacks<F> {
+    Callbacks(vec![])
+  }
+}
+
+impl<F: ?Sized> PartialEq for Callbacks<F> {
+  fn eq(&self, _: &Callbacks<F>) -> bool { true }
+}
+
+impl<F: ?Sized> Eq for Callbacks<F> {}
+
+impl<F: ?Sized> Hash for Callbacks<F> {
+  fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+impl<F: ?Sized> fmt::Debug for Callbacks<F> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("Callbacks")
+  }
+}
+
+// Where `Config::run_cached` records timings for `try_compile` to collect,
+// since when it started. It is shared by the copies `Config::for_file` makes,
+// and isn't part of the identity of the `Config`.
+#[derive(Clone, Default)]
+struct Trace(Arc<Mutex<(Option<Instant>, Vec<Timing>)>>);
+
+impl Trace {
+  fn start(&self) {
+    *self.0.lock().unwrap() = (Some(Instant::now()), vec![]);
+  }
+
+  fn record(&self, mut timing: Timing, start: Instant) {
+    let mut log = self.0.lock().unwrap();
+    let epoch = *log.0.get_or_insert(start);
+    timing.start = start.saturating_duration_since(epoch);
+    log.1.push(timing);
+  }
+
+  fn take(&self) -> Vec<Timing> {
+    let mut timings = std::mem::take(&mut self.0.lock().unwrap().1);
+    timings.sort_by_key(|t| t.start);
+    timings
+  }
+}
+
+impl PartialEq for Trace {
+  fn eq(&self, _: &Trace) -> bool { true }
+}
+
+impl Eq for Trace {}
+
+impl Hash for Trace {
+  fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+impl fmt::Debug for Trace {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("Trace")
+  }
+}
+
+/// An addressing scheme. By default, the addressing scheme matches the pointer
+/// width of Cargo's current target. If your arrays grow to more than `2^32`
+/// elements, this will need to be 64-bit.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Addr {
+  /// 32-bit or 64-bit addressing, following the target's pointer width
+  Auto,
   /// 32-bit addressing
   A32,
   /// 64-bit addressing
@@ -106,19 +310,61 @@ pub enum Addr {
 }
 
 /// The architecture to target. This will generally be autodetected from Cargo's
-/// current target, but may be changed manually to either x86 or x86_64 systems.
+/// current target, but may be changed manually to either x86, x86_64, ARM or
+/// wasm32 systems. GPU code is always built for `Xe64`.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Arch {
   /// x86
   X86,
   /// x86-64
-  X86_64
+  X86_64,
+  /// 32-bit ARM
+  Arm,
+  /// 64-bit ARM
+  Aarch64,
+  /// WebAssembly with the SIMD128 extension
+  Wasm32,
+  /// Intel Xe GPUs. Selected automatically by `Config::emit_spirv`.
+  Xe64,
+}
+
+/// The syntax of x86 assembly written by `Config::emit_assembly`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AsmSyntax {
+  /// Intel syntax, destination operand first
+  Intel,
+  /// AT&T syntax, as used by GNU `as`
+  Att,
+}
+
+impl AsmSyntax {
+  fn to_str(self) -> &'static str {
+    match self {
+      AsmSyntax::Intel => "intel",
+      AsmSyntax::Att   => "att",
+    }
+  }
+}
+
+/// Whether ispc's diagnostics are colored with ANSI escapes.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ColorChoice {
+  /// Color when cargo would, and the output is going to a terminal
+  Auto,
+  /// Always color
+  Always,
+  /// Never color
+  Never,
 }
 
 /// The CPU families one may generate code for. This will generally be
 /// autodetected based on the current set of ispc targets, but may be manually
 /// overridden.
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Cpu {
   /// generic
   Generic,
@@ -140,12 +386,63 @@ pub enum Cpu {
   Broadwell,
   /// slm/silvermont
   Slm,
+  /// knl
+  Knl,
+  /// skylake
+  Skylake,
+  /// skx
+  Skx,
+  /// icelake-client/icl
+  Icelake_client,
+  /// icelake-server/icx
+  Icelake_server,
+  /// tigerlake/tgl
+  Tigerlake,
+  /// alderlake/adl
+  Alderlake,
+  /// sapphirerapids/spr
+  Sapphirerapids,
+  /// btver2/ps4
+  Btver2,
+  /// znver1
+  Znver1,
+  /// znver2/ps5
+  Znver2,
+  /// znver3
+  Znver3,
+  /// cortex-a9
+  Cortex_a9,
+  /// cortex-a15
+  Cortex_a15,
+  /// cortex-a35
+  Cortex_a35,
+  /// cortex-a53
+  Cortex_a53,
+  /// cortex-a57
+  Cortex_a57,
+  /// apple-a7
+  Apple_a7,
+  /// apple-a10
+  Apple_a10,
+  /// apple-a11
+  Apple_a11,
+  /// apple-a12
+  Apple_a12,
+  /// apple-a13
+  Apple_a13,
+  /// apple-a14
+  Apple_a14,
+  /// apple-m1
+  Apple_m1,
+  /// Any other `--cpu` name understood by the installed ispc, passed through
+  /// verbatim.
+  Custom(String),
 }
 
 impl Cpu {
-  fn to_str(self) -> &'static str {
+  fn to_str(&self) -> &str {
     use Cpu::*;
-    match self {
+    match *self {
       Generic    => "generic",
       Atom       => "atom",
       Core2      => "core2",
@@ -156,12 +453,71 @@ impl Cpu {
       Core_avx2  => "core-avx2",
       Broadwell  => "broadwell",
       Slm        => "slm",
+      Knl            => "knl",
+      Skylake        => "skylake",
+      Skx            => "skx",
+      Icelake_client => "icelake-client",
+      Icelake_server => "icelake-server",
+      Tigerlake      => "tigerlake",
+      Alderlake      => "alderlake",
+      Sapphirerapids => "sapphirerapids",
+      Btver2         => "btver2",
+      Znver1         => "znver1",
+      Znver2         => "znver2",
+      Znver3         => "znver3",
+      Cortex_a9      => "cortex-a9",
+      Cortex_a15     => "cortex-a15",
+      Cortex_a35     => "cortex-a35",
+      Cortex_a53     => "cortex-a53",
+      Cortex_a57     => "cortex-a57",
+      Apple_a7       => "apple-a7",
+      Apple_a10      => "apple-a10",
+      Apple_a11      => "apple-a11",
+      Apple_a12      => "apple-a12",
+      Apple_a13      => "apple-a13",
+      Apple_a14      => "apple-a14",
+      Apple_m1       => "apple-m1",
+      Custom(ref s)  => s,
+    }
+  }
+}
+
+/// The code model to generate code for, which bounds how far apart code and
+/// data may be placed in the final binary.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CodeModel {
+  /// Everything must fit within the lower 2GB of the address space
+  Small,
+  /// No assumptions are made about addresses. Slower, but needed by very large
+  /// binaries that hit relocation overflows
+  Large,
+}
+
+impl CodeModel {
+  fn to_str(self) -> &'static str {
+    match self {
+      CodeModel::Small => "small",
+      CodeModel::Large => "large",
     }
   }
 }
 
+/// Selects whether LLVM IR is written out alongside the object files.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LlvmOutput {
+  /// Don't write out any LLVM IR
+  None,
+  /// Write out LLVM bitcode, as `.bc` files
+  Bitcode,
+  /// Write out textual LLVM IR, as `.ll` files
+  Text,
+}
+
 /// Selects which math libraries to call out to.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Math {
   /// Use ispc's built-in math functions
   Default,
@@ -186,10 +542,97 @@ impl Math {
   }
 }
 
+/// An individual ispc optimization switch, passed as `--opt=<name>`.
+///
+/// Most of these turn off an optimization, and are mostly useful for tracking
+/// down miscompilations or measuring what an optimization buys you.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OptFlag {
+  /// Remove `assert` statements from the generated code
+  Disable_assertions,
+  /// Don't generate fused multiply-add instructions
+  Disable_fma,
+  /// Don't unroll loops
+  Disable_loop_unroll,
+  /// Faster masked vector loads on SSE. May read off the end of arrays
+  Fast_masked_vload,
+  /// Non-IEEE-754 compliant math operations
+  Fast_math,
+  /// Always emit aligned vector loads and stores
+  Force_aligned_memory,
+  /// Avoid 512-bit `zmm` registers on AVX-512 targets
+  Disable_zmm,
+  /// Don't use gather instructions
+  Disable_gathers,
+  /// Don't use scatter instructions
+  Disable_scatters,
+  /// Don't generate coherent control flow for `cif`, `cfor` and friends
+  Disable_coherent_control_flow,
+  /// Don't special-case control flow on uniform conditions
+  Disable_uniform_control_flow,
+  /// Don't blend masked stores into full vector stores
+  Disable_blended_masked_stores,
+  /// Don't turn gathers and scatters into vector loads and stores
+  Disable_gather_scatter_optimizations,
+  /// Don't optimize for masks that are known to be all on
+  Disable_all_on_optimizations,
+  /// Don't lower pseudo memory operations
+  Disable_handle_pseudo_memory_ops,
+  /// Don't optimize masked stores
+  Disable_masked_store_optimizations,
+  /// Don't optimize memory accesses with uniform addresses
+  Disable_uniform_memory_optimizations,
+  /// Clear the flush-to-zero and denormals-are-zero modes on entry to exported
+  /// functions, restoring them on exit
+  Reset_ftz_daz,
+}
+
+impl OptFlag {
+  fn to_str(self) -> &'static str {
+    use OptFlag::*;
+    match self {
+      Disable_assertions                   => "disable-assertions",
+      Disable_fma                          => "disable-fma",
+      Disable_loop_unroll                  => "disable-loop-unroll",
+      Fast_masked_vload                    => "fast-masked-vload",
+      Fast_math                            => "fast-math",
+      Force_aligned_memory                 => "force-aligned-memory",
+      Disable_zmm                          => "disable-zmm",
+      Disable_gathers                      => "disable-gathers",
+      Disable_scatters                     => "disable-scatters",
+      Disable_coherent_control_flow        => "disable-coherent-control-flow",
+      Disable_uniform_control_flow         => "disable-uniform-control-flow",
+      Disable_blended_masked_stores        => "disable-blended-masked-stores",
+      Disable_gather_scatter_optimizations => "disable-gather-scatter-optimizations",
+      Disable_all_on_optimizations         => "disable-all-on-optimizations",
+      Disable_handle_pseudo_memory_ops     => "disable-handle-pseudo-memory-ops",
+      Disable_masked_store_optimizations   => "disable-masked-store-optimizations",
+      Disable_uniform_memory_optimizations => "disable-uniform-memory-optimizations",
+      Reset_ftz_daz                        => "reset-ftz-daz",
+    }
+  }
+}
+
+/// Selects what kind of relocatable code is generated, which must match how
+/// the final binary is linked.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Pic {
+  /// Position-dependent code, for static non-PIE binaries and bare-metal targets
+  None,
+  /// Position-independent code, which can be linked into anything
+  Pic,
+  /// Code for position-independent executables, including static-pie ones.
+  /// ispc has no separate PIE mode, so this generates position-independent code
+  Pie,
+}
+
 /// Selects which target ISA(s) and the lane width(s) to generate code for.
 ///
 /// Only one width per ISA may be selected.
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Target {
   /// SSE2, auto-detect lane width
   Sse2,
@@ -237,12 +680,78 @@ pub enum Target {
   Avx2_i32x16,
   /// AVX 2.0, x4 `i64`s processed at once
   Avx2_i64x4,
+
+  /// AVX-512 (Knights Landing), x16 `i32`s processed at once
+  Avx512knl_i32x16,
+
+  /// AVX-512 (Skylake), x8 `i32`s processed at once
+  Avx512skx_i32x8,
+  /// AVX-512 (Skylake), x16 `i32`s processed at once
+  Avx512skx_i32x16,
+
+  /// NEON, x16 `i8`s processed at once. Requires `Arch::Arm` or `Arch::Aarch64`.
+  Neon_i8x16,
+  /// NEON, x8 `i16`s processed at once. Requires `Arch::Arm` or `Arch::Aarch64`.
+  Neon_i16x8,
+  /// NEON, x4 `i32`s processed at once. Requires `Arch::Arm` or `Arch::Aarch64`.
+  Neon_i32x4,
+  /// NEON, x8 `i32`s processed at once. Requires `Arch::Arm` or `Arch::Aarch64`.
+  Neon_i32x8,
+
+  /// WebAssembly SIMD128, x4 `i32`s processed at once. Requires `Arch::Wasm32`
+  /// and may not be combined with any other target.
+  Wasm_i32x4,
+
+  /// Intel Gen9 GPU, x8 lanes. Requires `Config::emit_spirv`.
+  Gen9_x8,
+  /// Intel Gen9 GPU, x16 lanes. Requires `Config::emit_spirv`.
+  Gen9_x16,
+  /// Intel Xe-LP GPU, x8 lanes. Requires `Config::emit_spirv`.
+  Xelp_x8,
+  /// Intel Xe-LP GPU, x16 lanes. Requires `Config::emit_spirv`.
+  Xelp_x16,
+  /// Intel Xe-HPG GPU, x8 lanes. Requires `Config::emit_spirv`.
+  Xehpg_x8,
+  /// Intel Xe-HPG GPU, x16 lanes. Requires `Config::emit_spirv`.
+  Xehpg_x16,
+  /// Intel Xe-HPC GPU, x16 lanes. Requires `Config::emit_spirv`.
+  Xehpc_x16,
+  /// Intel Xe-HPC GPU, x32 lanes. Requires `Config::emit_spirv`.
+  Xehpc_x32,
+
+  /// Portable C++, x1 lanes. See `Config::cxx_include_file`.
+  Generic_x1,
+  /// Portable C++, x4 lanes. See `Config::cxx_include_file`.
+  Generic_x4,
+  /// Portable C++, x8 lanes. See `Config::cxx_include_file`.
+  Generic_x8,
+  /// Portable C++, x16 lanes. See `Config::cxx_include_file`.
+  Generic_x16,
+  /// Portable C++, x32 lanes. See `Config::cxx_include_file`.
+  Generic_x32,
+  /// Portable C++, x64 lanes. See `Config::cxx_include_file`.
+  Generic_x64,
+
+  /// Intel Xeon Phi coprocessors (Knights Corner), x16 lanes.
+  ///
+  /// This is built through ispc's C++ backend like the `Generic_*` targets, so
+  /// `Config::cxx_include_file` must point at the `knc.h` header from ispc's
+  /// `examples/intrinsics`, and `CXX` at the Intel C++ compiler, which is passed
+  /// `-mmic`. The resulting code only runs on the coprocessor itself, so the
+  /// rest of the crate must be built for it too, and `rispcrt`'s task system is
+  /// not available there.
+  Knc,
+
+  /// Any other `--target` string understood by the installed ispc. It is passed
+  /// through verbatim, so ispc itself is left to reject it if it's invalid. See
+  /// `Config::custom_target` to control the object suffix it produces.
+  Custom(String),
 }
 
 impl Target {
-  fn to_str(self) -> &'static str {
+  fn to_str(&self) -> &str {
     use Target::*;
-    match self {
+    match *self {
       Sse2       => "sse2",
       Sse2_i32x4 => "sse2-i32x4",
       Sse2_i32x8 => "sse2-i32x8",
@@ -268,520 +777,5426 @@ impl Target {
       Avx2_i32x8  => "avx2-i32x8",
       Avx2_i32x16 => "avx2-i32x16",
       Avx2_i64x4  => "avx2-i64x4",
+
+      Avx512knl_i32x16 => "avx512knl-i32x16",
+
+      Avx512skx_i32x8  => "avx512skx-i32x8",
+      Avx512skx_i32x16 => "avx512skx-i32x16",
+
+      Neon_i8x16 => "neon-i8x16",
+      Neon_i16x8 => "neon-i16x8",
+      Neon_i32x4 => "neon-i32x4",
+      Neon_i32x8 => "neon-i32x8",
+
+      Wasm_i32x4 => "wasm-i32x4",
+
+      Gen9_x8   => "gen9-x8",
+      Gen9_x16  => "gen9-x16",
+      Xelp_x8   => "xelp-x8",
+      Xelp_x16  => "xelp-x16",
+      Xehpg_x8  => "xehpg-x8",
+      Xehpg_x16 => "xehpg-x16",
+      Xehpc_x16 => "xehpc-x16",
+      Xehpc_x32 => "xehpc-x32",
+
+      Generic_x1  => "generic-x1",
+      Generic_x4  => "generic-x4",
+      Generic_x8  => "generic-x8",
+      Generic_x16 => "generic-x16",
+      Generic_x32 => "generic-x32",
+      Generic_x64 => "generic-x64",
+
+      Knc => "knc",
+
+      Custom(ref s) => s,
+    }
+  }
+
+  // The suffix ispc appends to the object file it emits for this target when
+  // more than one target is selected.
+  fn obj_suffix(&self) -> String {
+    use Target::*;
+    match *self {
+      Sse2 | Sse2_i32x4 | Sse2_i32x8 => "sse2".into(),
+      Sse4 | Sse4_i32x4 | Sse4_i32x8 | Sse4_i16x8 | Sse4_i8x16 => "sse4".into(),
+      Avx1 | Avx1_i32x4 | Avx1_i32x8 | Avx1_i32x16 | Avx1_i64x4 => "avx".into(),
+      Avx1_1 | Avx1_1_i32x8 | Avx1_1_i32x16 | Avx1_1_i64x4 => "avx11".into(),
+      Avx2 | Avx2_i32x8 | Avx2_i32x16 | Avx2_i64x4 => "avx2".into(),
+      Avx512knl_i32x16 => "avx512knl".into(),
+      Avx512skx_i32x8 | Avx512skx_i32x16 => "avx512skx".into(),
+      _ => self.to_str().split('-').next().unwrap().replace(".", ""),
+    }
+  }
+
+  // Orders the x86 ISAs by the set of instructions they require, so that a
+  // target can be compared against what the Rust code is allowed to assume.
+  fn isa_level(&self) -> Option<u32> {
+    match &*self.obj_suffix() {
+      "sse2"  => Some(0),
+      "sse4"  => Some(1),
+      "avx"   => Some(2),
+      "avx11" => Some(3),
+      "avx2"  => Some(4),
+      "avx512skx" => Some(5),
+      _       => None,
+    }
+  }
+
+  // The CPU features `is_x86_feature_detected!` must find to run this target,
+  // for `Config::rust_dispatch`.
+  fn features(&self) -> Option<&'static [&'static str]> {
+    match &*self.obj_suffix() {
+      "sse2"  => Some(&["sse2"]),
+      "sse4"  => Some(&["sse4.1", "sse4.2"]),
+      "avx"   => Some(&["avx"]),
+      "avx11" => Some(&["avx", "f16c", "rdrand"]),
+      "avx2"  => Some(&["avx2", "fma", "f16c"]),
+      "avx512skx" => Some(&["avx512f", "avx512cd", "avx512bw", "avx512dq", "avx512vl"]),
+      _       => None,
     }
   }
+
+  fn is_generic(&self) -> bool {
+    use Target::*;
+    matches!(*self, Generic_x1 | Generic_x4 | Generic_x8 | Generic_x16 | Generic_x32 |
+                    Generic_x64 | Knc)
+  }
 }
 
-/// Extra configuration to pass to `ispc`.
-#[derive(PartialEq, Eq, Hash, Debug)]
-pub struct Config {
-  addressing: Option<Addr>,
-  architecture: Option<Arch>,
-  cpu: Option<Vec<Cpu>>,
-  definitions: Vec<(String, Option<String>)>,
-  force_alignment: Option<u32>,
-  debug: Option<bool>,
-  math_lib: Math,
-  files: Vec<PathBuf>,
-  opt_level: Option<u32>,
-  assertations: bool,
-  fma: bool,
-  loop_unroll: bool,
-  fast_masked_vload: bool,
-  fast_math: bool,
-  force_aligned_memory: bool,
-  pic: Option<bool>,
-  targets: Option<Vec<Target>>,
-  werror: bool,
-  warnings: bool,
-  wperf: bool,
+/// The operating system to generate code for. This will generally be
+/// autodetected from Cargo's current target, and determines the object file
+/// format produced.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TargetOs {
+  /// Windows (COFF)
+  Windows,
+  /// Linux (ELF)
+  Linux,
+  /// FreeBSD (ELF)
+  Freebsd,
+  /// macOS (Mach-O)
+  Macos,
+  /// Android (ELF)
+  Android,
+  /// iOS (Mach-O)
+  Ios,
+  /// PlayStation 4 (ELF)
+  Ps4,
+  /// The web, for wasm32
+  Web,
 }
 
-impl Config {
-  /// Constructs a new instance of a blank set of configuration.
-  ///
-  /// The builder is finished with the `compile` function.
-  pub fn new() -> Config {
-    Config {
-      addressing: None,
-      architecture: None,
-      cpu: None,
-      definitions: vec![],
-      force_alignment: None,
-      debug: None,
-      math_lib: Math::Default,
-      files: vec![],
-      opt_level: None,
-      assertations: true,
-      fma: true,
-      loop_unroll: true,
-      fast_masked_vload: false,
-      fast_math: false,
-      force_aligned_memory: false,
-      pic: None,
-      targets: None,
-      werror: true,
-      warnings: true,
-      wperf: true,
+impl TargetOs {
+  fn to_str(self) -> &'static str {
+    use TargetOs::*;
+    match self {
+      Windows => "windows",
+      Linux   => "linux",
+      Freebsd => "freebsd",
+      Macos   => "macos",
+      Android => "android",
+      Ios     => "ios",
+      Ps4     => "ps4",
+      Web     => "web",
     }
   }
 
-  /// Sets the addressing mode of the compiled ispc code.
-  ///
-  /// By default, all ispc-generated code is addressed with `i32`s, so if your
-  /// arrays have more than 2 billion elements, you'll need to change the
-  /// addressing mode to `Addr::X86_64`.
-  ///
-  /// Default value: `Addr::X86`
-  pub fn addressing(&mut self, a: Addr) -> &mut Self {
-    self.addressing = Some(a);
-    self
+}
+
+/// How much the build script prints. Cargo only shows this output for failed
+/// builds, or when run with `-vv`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Verbosity {
+  /// Only print the output of commands that fail
+  Silent,
+  /// Print every command run, and anything it outputs
+  Normal,
+  /// Also print every environment variable read and every exit status
+  Verbose,
+}
+
+/// A version of ispc, such as `1.18.0`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Version {
+  /// The major version
+  pub major: u32,
+  /// The minor version
+  pub minor: u32,
+  /// The patch version, or `0` if there is none
+  pub patch: u32,
+}
+
+impl Version {
+  // Parses `1.18` or `1.18.0`, ignoring anything after the digits of each part
+  // such as the `dev` of `1.19.0dev`.
+  fn parse(s: &str) -> Option<Version> {
+    let mut parts = s.split('.').map(|p| {
+      let digits: String = p.chars().take_while(|c| c.is_ascii_digit()).collect();
+      digits.parse::<u32>().ok()
+    });
+    let major = match parts.next() { Some(Some(v)) => v, _ => return None };
+    let minor = match parts.next() { Some(Some(v)) => v, _ => return None };
+    let patch = match parts.next() { Some(Some(v)) => v, Some(None) => return None, None => 0 };
+    Some(Version { major: major, minor: minor, patch: patch })
   }
+}
 
-  /// Adds an entry to the target CPU set.
-  ///
-  /// Code will be generated for all CPUs in the target CPU set, and the correct
-  /// version to run will be autodetected at run time.
-  ///
-  /// By default, the target CPU set is empty, as it can be automaatically
-  /// determined by the selected `target`s.
-  ///
-  /// Default value: `[]`
-  pub fn cpu(&mut self, c: Cpu) -> &mut Self {
-    if self.cpu.is_none() { self.cpu = Some(vec![]); }
-    self.cpu.as_mut().map(|cs| cs.push(c));
-    self
+impl fmt::Display for Version {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
   }
+}
 
-  /// Specifies a `-D` variable with an optional value.
-  ///
-  /// Default value: `[]`
-  pub fn define(&mut self, k: &str, v: Option<&str>) -> &mut Self {
-    self.definitions.push((k.into(), v.map(|v| v.into())));
-    self
+/// A warning, or family of warnings, that ispc may emit. See
+/// `Config::warning`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Warning {
+  /// Performance warnings about gathers
+  Gather,
+  /// Performance warnings about scatters
+  Scatter,
+  /// Performance warnings about slow conversions from unsigned integers to
+  /// floating point
+  Unsigned_to_float,
+  /// Performance warnings about `%` with varying operands
+  Varying_modulus,
+  /// Any warning containing the given text
+  Custom(String),
+}
+
+impl Warning {
+  fn matches(&self, msg: &str) -> bool {
+    use Warning::*;
+    match *self {
+      Gather            => msg.contains("Gather required"),
+      Scatter           => msg.contains("Scatter required"),
+      Unsigned_to_float => msg.contains("Conversion from unsigned"),
+      Varying_modulus   => msg.contains("Modulus operator with varying types"),
+      Custom(ref s)     => msg.contains(&**s),
+    }
   }
+}
 
-  /// Turns on or off generation of debug info.
-  ///
-  /// This will generally be automatically determined by the currently selected
-  /// cargo profile.
-  pub fn debug(&mut self, val: bool) -> &mut Self {
-    self.debug = Some(val);
-    self
+/// Describes how to build for the Rust target triples matching a pattern.
+///
+/// Every property of a rule is optional. Each property of the build is taken
+/// from the first matching rule which sets it, searching the rules added with
+/// `Config::triple_rule` before the built-in ones. A build may therefore mix
+/// the architecture of one rule with the OS of another.
+///
+/// # Examples
+///
+/// ```no_run
+/// let mut rule = rispc::TripleRule::new("x86_64-*-myconsole*");
+/// rule.arch(rispc::Arch::X86_64)
+///     .target(rispc::Target::Avx1)
+///     .target_os(rispc::TargetOs::Ps4)
+///     .pic(false);
+///
+/// rispc::Config::new()
+///   .triple_rule(&rule)
+///   .file("src/mandelbrot.ispc")
+///   .compile("mandelbrot");
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TripleRule {
+  pattern: String,
+  arch: Option<Arch>,
+  targets: Vec<Target>,
+  target_os: Option<TargetOs>,
+  pic: Option<Pic>,
+}
+
+impl TripleRule {
+  /// Constructs a rule for the triples matching `pattern`, in which `*` matches
+  /// any sequence of characters.
+  pub fn new(pattern: &str) -> TripleRule {
+    TripleRule {
+      pattern: pattern.into(),
+      arch: None,
+      targets: vec![],
+      target_os: None,
+      pic: None,
+    }
   }
 
-  /// Selects the math library to call out to.
-  ///
-  /// Default value: `Math::Default`
-  pub fn math_lib(&mut self, m: Math) -> &mut Self {
-    self.math_lib = m;
+  /// Sets the architecture to build for.
+  pub fn arch(&mut self, a: Arch) -> &mut Self {
+    self.arch = Some(a);
     self
   }
 
-  /// Adds a file to the set of files to be compiled together.
-  ///
-  /// Default value: `[]`
-  pub fn file<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
-    self.files.push(p.as_ref().to_path_buf());
+  /// Adds a target to the default target list.
+  pub fn target(&mut self, t: Target) -> &mut Self {
+    self.targets.push(t);
     self
   }
 
-  /// Set the optimization level.
-  ///
-  /// Default value: inferred from current cargo profile
-  pub fn opt_level(&mut self, level: u32) -> &mut Self {
-    self.opt_level = Some(level);
+  /// Sets the operating system to build for.
+  pub fn target_os(&mut self, os: TargetOs) -> &mut Self {
+    self.target_os = Some(os);
     self
   }
 
-  /// Enables or disables asssertations in the code.
-  ///
-  /// Default value: `true`
-  pub fn enable_assertations(&mut self, val: bool) -> &mut Self {
-    self.assertations = val;
+  /// Sets whether position-independent code is generated by default.
+  pub fn pic(&mut self, val: bool) -> &mut Self {
+    self.pic_mode(if val { Pic::Pic } else { Pic::None })
+  }
+
+  /// Sets the kind of relocatable code generated by default.
+  pub fn pic_mode(&mut self, pic: Pic) -> &mut Self {
+    self.pic = Some(pic);
     self
   }
 
-  /// Enables or disables generation of fused multiply-add instructions.
-  ///
-  /// Default value: `true`
-  pub fn enable_fma(&mut self, val: bool) -> &mut Self {
-    self.fma = val;
+  fn matches(&self, triple: &str) -> bool {
+    let parts: Vec<&str> = self.pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+
+    if parts.len() == 1 { return triple == first; }
+    if triple.len() < first.len() + last.len() { return false; }
+    if !triple.starts_with(first) || !triple.ends_with(last) { return false; }
+
+    let mut rest = &triple[first.len()..triple.len() - last.len()];
+    for p in &parts[1..parts.len() - 1] {
+      match rest.find(p) {
+        Some(i) => rest = &rest[i + p.len()..],
+        None    => return false,
+      }
+    }
+    true
+  }
+}
+
+fn builtin_triple_rules() -> Vec<TripleRule> {
+  let x86 = [ Target::Sse2, Target::Sse4, Target::Avx1, Target::Avx1_1, Target::Avx2 ];
+  let mut rules = vec![];
+
+  fn rule<'a>(rules: &'a mut Vec<TripleRule>, pattern: &str) -> &'a mut TripleRule {
+    rules.push(TripleRule::new(pattern));
+    rules.last_mut().unwrap()
+  }
+
+  // Like rustc, generate PIC everywhere except bare-metal targets.
+  rule(&mut rules, "*-none*").pic(false);
+  rule(&mut rules, "*-apple-ios*").target_os(TargetOs::Ios);
+  rule(&mut rules, "*-apple-*").target_os(TargetOs::Macos);
+  rule(&mut rules, "*-windows*").target_os(TargetOs::Windows);
+  rule(&mut rules, "*-android*").target_os(TargetOs::Android);
+  rule(&mut rules, "*-freebsd*").target_os(TargetOs::Freebsd);
+  rule(&mut rules, "*-scei-ps4*").target_os(TargetOs::Ps4);
+  rule(&mut rules, "*-linux*").target_os(TargetOs::Linux);
+
+  for &(p, arch) in &[ ("x86_64*", Arch::X86_64),
+                       ("i686*",   Arch::X86),
+                       ("i586*",   Arch::X86) ] {
+    let r = rule(&mut rules, p).arch(arch);
+    for t in x86.iter() { r.target(t.clone()); }
+  }
+
+  rule(&mut rules, "wasm32*").arch(Arch::Wasm32).target(Target::Wasm_i32x4).target_os(TargetOs::Web);
+  rule(&mut rules, "aarch64*").arch(Arch::Aarch64).target(Target::Neon_i32x4);
+  rule(&mut rules, "arm*").arch(Arch::Arm).target(Target::Neon_i32x4);
+  rule(&mut rules, "thumb*").arch(Arch::Arm).target(Target::Neon_i32x4);
+
+  rules
+}
+
+/// Settings which override those of the `Config` for a single source file. See
+/// `Config::file_with`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FileOpts {
+  definitions: Vec<(String, Option<String>)>,
+  opts: Vec<(OptFlag, bool)>,
+  math_lib: Option<Math>,
+  targets: Option<Vec<Target>>,
+}
+
+impl FileOpts {
+  /// Adds a preprocessor definition, replacing any definition of the same
+  /// name from the `Config`.
+  pub fn define(&mut self, k: &str, v: Option<&str>) -> &mut Self {
+    self.definitions.push((k.into(), v.map(|v| v.into())));
     self
   }
 
-  /// Enables or disables loop unrolling.
+  /// Turns an ispc optimization switch on or off, as `Config::opt` does.
+  pub fn opt(&mut self, flag: OptFlag, val: bool) -> &mut Self {
+    self.opts.push((flag, val));
+    self
+  }
+
+  /// Selects the math library for this file.
+  pub fn math_lib(&mut self, m: Math) -> &mut Self {
+    self.math_lib = Some(m);
+    self
+  }
+
+  /// Adds a target to build this file for. The first target added replaces
+  /// the targets of the `Config`.
+  pub fn target(&mut self, t: Target) -> &mut Self {
+    self.targets.get_or_insert_with(Vec::new).push(t);
+    self
+  }
+}
+
+/// Where the shared cache keeps compiled objects. See `Config::cache_backend`.
+///
+/// Keys are made of ASCII letters, digits and `.`. A backend may drop entries
+/// whenever it likes, and must tolerate being called from several threads and
+/// several build scripts at once.
+pub trait CacheBackend: Send + Sync {
+  /// The value last stored under `key`, if there is one.
+  fn get(&self, key: &str) -> Option<Vec<u8>>;
+
+  /// Stores `value` under `key`. An error only means the build misses out on
+  /// the cache.
+  fn put(&self, key: &str, value: &[u8]) -> io::Result<()>;
+}
+
+/// A `CacheBackend` keeping each entry as a file in a local directory.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct DirCache {
+  dir: PathBuf,
+}
+
+impl DirCache {
+  /// Keeps entries in `dir`, which is created when needed.
+  pub fn new<P: AsRef<Path>>(dir: P) -> DirCache {
+    DirCache { dir: dir.as_ref().to_path_buf() }
+  }
+}
+
+impl CacheBackend for DirCache {
+  fn get(&self, key: &str) -> Option<Vec<u8>> {
+    fs::read(self.dir.join(key)).ok()
+  }
+
+  // Written under a name of its own, then renamed into place, so that
+  // concurrent builds never read a partial entry.
+  fn put(&self, key: &str, value: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(&self.dir)?;
+    let tmp = self.dir.join(format!("{}.{}.tmp", key, std::process::id()));
+    fs::write(&tmp, value)?;
+    if let Err(e) = fs::rename(&tmp, self.dir.join(key)) {
+      let _ = fs::remove_file(&tmp);
+      return Err(e);
+    }
+    Ok(())
+  }
+}
+
+/// What `Config::try_compile` built.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct CompileOutput {
+  archive: Option<PathBuf>,
+  objects: Vec<PathBuf>,
+  header: Option<PathBuf>,
+  timings: Vec<Timing>,
+  variants: Vec<(String, CompileOutput)>,
+  text_sizes: Vec<(Target, u64)>,
+}
+
+impl CompileOutput {
+  /// The static archive, or the shared library of `compile_shared`, unless
+  /// emitting SPIR-V, compiling was skipped, or ispc is missing.
+  pub fn archive(&self) -> Option<&Path> {
+    self.archive.as_deref()
+  }
+
+  /// The files the sources were compiled to: object files, including those for
+  /// each target of a multi-target build and those compiled from the C++
+  /// emitted for `Generic_*` targets, or `.spv` modules. These are what the
+  /// archive or shared library holds.
+  pub fn objects(&self) -> &[PathBuf] {
+    &self.objects
+  }
+
+  /// The header including those of every source file, unless `emit_header` or
+  /// `emit_spirv` says otherwise.
+  pub fn header(&self) -> Option<&Path> {
+    self.header.as_deref()
+  }
+
+  /// How long each run of ispc took, in the order they started.
+  pub fn timings(&self) -> &[Timing] {
+    &self.timings
+  }
+
+  /// The size of the code compiled for each target, summed over every file, as
+  /// `Config::text_size_budget` checks. Targets whose objects don't say are
+  /// left out, as are the dispatchers of multi-target builds.
+  pub fn text_sizes(&self) -> &[(Target, u64)] {
+    &self.text_sizes
+  }
+
+  /// What was built for the variant `name`. See `Config::variant`.
+  pub fn variant(&self, name: &str) -> Option<&CompileOutput> {
+    self.variants.iter().find(|v| v.0 == name).map(|v| &v.1)
+  }
+
+  /// The warnings and performance warnings of every run of ispc, each only
+  /// once, though ispc repeats them for every target.
+  pub fn diagnostics(&self) -> Vec<Diagnostic> {
+    let mut ds = vec![];
+    for d in self.timings.iter().flat_map(|t| &t.diagnostics) {
+      if !ds.contains(d) { ds.push(d.clone()); }
+    }
+    ds
+  }
+}
+
+/// What compiling a single source file produced. See `Config::after_compile`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct CompiledObject {
+  objects: Vec<PathBuf>,
+  cpp: Option<PathBuf>,
+  header: Option<PathBuf>,
+}
+
+impl CompiledObject {
+  /// The object files, including those for each target of a multi-target
+  /// build, or the `.spv` module. Empty for `Generic_*` targets.
+  pub fn objects(&self) -> &[PathBuf] {
+    &self.objects
+  }
+
+  /// The C++ emitted for `Generic_*` targets, which is compiled later.
+  pub fn cpp(&self) -> Option<&Path> {
+    self.cpp.as_deref()
+  }
+
+  /// The header for the file, unless `emit_header` says otherwise.
+  pub fn header(&self) -> Option<&Path> {
+    self.header.as_deref()
+  }
+}
+
+/// How serious a `Diagnostic` is.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Severity {
+  /// A performance warning, such as a gather ispc couldn't avoid
+  PerformanceWarning,
+  /// A warning
+  Warning,
+  /// An error, failing the build
+  Error,
+}
+
+impl Severity {
+  fn to_str(self) -> &'static str {
+    match self {
+      Severity::PerformanceWarning => "Performance Warning",
+      Severity::Warning            => "Warning",
+      Severity::Error              => "Error",
+    }
+  }
+}
+
+/// A diagnostic printed by ispc, such as
+/// `kernel.ispc:12:5: Performance Warning: Gather required to load value.`
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Diagnostic {
+  file: Option<PathBuf>,
+  line: Option<u32>,
+  col: Option<u32>,
+  severity: Severity,
+  message: String,
+}
+
+impl Diagnostic {
+  /// The source file it is about, unless it is about none in particular.
+  pub fn file(&self) -> Option<&Path> {
+    self.file.as_deref()
+  }
+
+  /// The line in `file`, counting from 1.
+  pub fn line(&self) -> Option<u32> {
+    self.line
+  }
+
+  /// The column in `line`, counting from 1.
+  pub fn col(&self) -> Option<u32> {
+    self.col
+  }
+
+  /// How serious it is.
+  pub fn severity(&self) -> Severity {
+    self.severity
+  }
+
+  /// What ispc said, without the source snippet following it.
+  pub fn message(&self) -> &str {
+    &self.message
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(ref file) = self.file {
+      write!(f, "{}:", file.display())?;
+      if let (Some(line), Some(col)) = (self.line, self.col) { write!(f, "{}:{}:", line, col)?; }
+      f.write_str(" ")?;
+    }
+    write!(f, "{}: {}", self.severity.to_str(), self.message)
+  }
+}
+
+/// One run of ispc, or the reuse of what it compiled before. See
+/// `CompileOutput::timings`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Timing {
+  file: PathBuf,
+  output: PathBuf,
+  targets: String,
+  start: Duration,
+  duration: Duration,
+  compiled: bool,
+  diagnostics: Vec<Diagnostic>,
+  // Where and how ispc ran, for `Config::compile_commands`.
+  directory: PathBuf,
+  command: Vec<String>,
+}
+
+impl Timing {
+  /// The source file.
+  pub fn file(&self) -> &Path {
+    &self.file
+  }
+
+  /// The first file written, such as the object file.
+  pub fn output(&self) -> &Path {
+    &self.output
+  }
+
+  /// The targets compiled for, as passed to ispc, such as
+  /// `sse4-i32x4,avx2-i32x8`.
+  pub fn targets(&self) -> &str {
+    &self.targets
+  }
+
+  /// When it started, since the first run of the build.
+  pub fn start(&self) -> Duration {
+    self.start
+  }
+
+  /// How long it took.
+  pub fn duration(&self) -> Duration {
+    self.duration
+  }
+
+  /// Whether ispc ran, rather than the outputs being reused from the last
+  /// build or the shared cache.
+  pub fn compiled(&self) -> bool {
+    self.compiled
+  }
+
+  /// The warnings and performance warnings ispc printed, if it ran.
+  pub fn diagnostics(&self) -> &[Diagnostic] {
+    &self.diagnostics
+  }
+}
+
+/// A library built by the build script of another crate, as found by
+/// `import_prebuilt`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Prebuilt {
+  lib: PathBuf,
+  header: Option<PathBuf>,
+  include: Option<PathBuf>,
+  ispc_includes: Vec<PathBuf>,
+}
+
+impl Prebuilt {
+  /// The static library.
+  pub fn lib(&self) -> &Path {
+    &self.lib
+  }
+
+  /// The header including those of every source file, from which bindings may
+  /// be generated, unless it wasn't written.
+  pub fn header(&self) -> Option<&Path> {
+    self.header.as_deref()
+  }
+
+  /// The directory of `header`.
+  pub fn include(&self) -> Option<&Path> {
+    self.include.as_deref()
+  }
+
+  /// The directories the library's ispc code `#include`s from, for passing to
+  /// `Config::include`.
+  pub fn ispc_includes(&self) -> &[PathBuf] {
+    &self.ispc_includes
+  }
+}
+
+/// An error which stopped `Config::try_compile`.
+#[derive(Debug)]
+pub enum Error {
+  /// ispc could not be run, or isn't ispc.
+  MissingCompiler {
+    /// The program which was run.
+    path: PathBuf,
+    /// What went wrong.
+    reason: String,
+  },
+  /// The installed ispc doesn't satisfy `Config::require_version`.
+  UnsupportedVersion {
+    /// The installed version.
+    found: Version,
+    /// The requirement which failed, such as `>=1.18.0`.
+    required: String,
+  },
+  /// ispc can't build for the Rust target triple.
+  UnsupportedArch {
+    /// The triple, from `TARGET`.
+    triple: String,
+  },
+  /// The installed ispc doesn't support these targets.
+  UnsupportedTarget {
+    /// The names of the targets.
+    targets: Vec<String>,
+  },
+  /// ispc exited unsuccessfully.
+  CompilerExited {
+    /// How it exited.
+    status: ExitStatus,
+    /// Its diagnostics, less any disabled warnings.
+    stderr: String,
+  },
+  /// ispc ran for longer than `Config::timeout`, and was killed.
+  TimedOut {
+    /// How long it was given.
+    timeout: Duration,
+    /// What it printed until then, less any disabled warnings.
+    stderr: String,
+  },
+  /// ispc wrote different outputs when run twice on the same file, as found by
+  /// `Config::verify_determinism`.
+  Nondeterministic {
+    /// The source file.
+    file: PathBuf,
+    /// The outputs which differed.
+    outputs: Vec<PathBuf>,
+  },
+  /// ispc succeeded, but didn't write an object it should have.
+  MissingObject {
+    /// Where the object should be.
+    path: PathBuf,
+  },
+  /// ispc succeeded, but printed warnings which `werror` makes errors.
+  Warnings {
+    /// Whether these are performance warnings, failing due to `werror_perf`.
+    perf: bool,
+    /// ispc's diagnostics, less any disabled warnings.
+    stderr: String,
+  },
+  /// The configuration, or an environment variable, asks for something which
+  /// can't be built.
+  InvalidConfig(String),
+  /// An environment variable cargo sets for build scripts is missing.
+  MissingEnv(String),
+  /// The objects could not be archived.
+  Archive {
+    /// The name of the archive.
+    output: String,
+    /// What the archiver reported.
+    message: String,
+  },
+  /// The objects could not be linked into a shared library.
+  Link {
+    /// The shared library.
+    output: String,
+    /// What the compiler or linker reported.
+    message: String,
+  },
+  /// bindgen failed on the header.
+  Bindings {
+    /// The header including those of every source file.
+    header: PathBuf,
+  },
+  /// Reading or writing a file failed.
+  Io(io::Error),
+  /// Every problem found by `Config::check`.
+  Check(Vec<Error>),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Error::MissingCompiler { ref path, ref reason } =>
+        write!(f, "could not run ispc at `{}`: {}. Install it from https://ispc.github.io, \
+                   or set the ISPC environment variable to its path.", path.display(), reason),
+      Error::UnsupportedVersion { ref found, ref required } =>
+        write!(f, "ispc {} is installed, but version {} is required", found, required),
+      Error::UnsupportedArch { ref triple } =>
+        write!(f, "ispc can only target x86, x86_64, ARM or wasm32, not `{}`. \
+                   See `Config::triple_rule` to describe it.", triple),
+      Error::UnsupportedTarget { ref targets } =>
+        write!(f, "the installed ispc does not support the target(s) {}. Install a newer ispc, \
+                   select other targets, or enable `filter_unsupported_targets`.",
+               targets.join(", ")),
+      Error::CompilerExited { ref status, ref stderr } =>
+        write!(f, "command did not execute successfully, got: {}\n{}", status, stderr),
+      Error::TimedOut { ref timeout, ref stderr } =>
+        write!(f, "ispc was killed after running for {:?}\n{}", timeout, stderr),
+      Error::Nondeterministic { ref file, ref outputs } => {
+        let outputs: Vec<String> = outputs.iter().map(|o| format!("`{}`", o.display())).collect();
+        write!(f, "compiling `{}` twice wrote different {}", file.display(), outputs.join(", "))
+      },
+      Error::MissingObject { ref path } =>
+        write!(f, "ispc did not write the object `{}`", path.display()),
+      Error::Warnings { perf, ref stderr } =>
+        write!(f, "{}warnings treated as errors:\n{}", if perf { "performance " } else { "" }, stderr),
+      Error::InvalidConfig(ref m) => f.write_str(m),
+      Error::MissingEnv(ref v) => write!(f, "environment variable `{}` not defined", v),
+      Error::Archive { ref output, ref message } =>
+        write!(f, "failed to archive `{}`: {}", output, message),
+      Error::Link { ref output, ref message } =>
+        write!(f, "failed to link `{}`: {}", output, message),
+      Error::Bindings { ref header } =>
+        write!(f, "failed to generate bindings for `{}`", header.display()),
+      Error::Io(ref e) => e.fmt(f),
+      Error::Check(ref problems) => {
+        f.write_str("rispc cannot build this configuration:\n")?;
+        for p in problems { writeln!(f, "  - {}", p)?; }
+        Ok(())
+      },
+    }
+  }
+}
+
+impl Error {
+  /// The errors and warnings ispc printed, if it ran and failed or timed out.
+  pub fn diagnostics(&self) -> Vec<Diagnostic> {
+    match *self {
+      Error::CompilerExited { ref stderr, .. } |
+      Error::TimedOut { ref stderr, .. } |
+      Error::Warnings { ref stderr, .. } => parse_diagnostics(stderr),
+      Error::Check(ref problems) => problems.iter().flat_map(|p| p.diagnostics()).collect(),
+      _ => vec![],
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match *self {
+      Error::Io(ref e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for Error {
+  fn from(e: io::Error) -> Error {
+    Error::Io(e)
+  }
+}
+
+/// Extra configuration to pass to `ispc`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Config {
+  addressing: Addr,
+  architecture: Option<Arch>,
+  code_model: Option<CodeModel>,
+  color: ColorChoice,
+  cpu: Option<Vec<Cpu>>,
+  custom_suffixes: Vec<(String, String)>,
+  cxx_include_file: Option<PathBuf>,
+  definitions: Vec<(String, Option<String>)>,
+  target_definitions: Vec<(Target, String, Option<String>)>,
+  force_alignment: Option<u32>,
+  debug: Option<bool>,
+  path_remaps: Vec<(PathBuf, PathBuf)>,
+  dwarf_version: Option<u32>,
+  dll_export: Option<bool>,
+  emit_header: bool,
+  emit_spirv: bool,
+  emit_llvm: LlvmOutput,
+  emit_assembly: bool,
+  asm_syntax: Option<AsmSyntax>,
+  envs: Vec<(OsString, OsString)>,
+  current_dir: Option<PathBuf>,
+  math_lib: Math,
+  svml_path: Option<PathBuf>,
+  sysroot: Option<PathBuf>,
+  native: bool,
+  no_stdlib: bool,
+  no_cpp: bool,
+  no_omit_frame_pointer: bool,
+  shared_cache: Option<bool>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  cache_backend: Backend,
+  features: bool,
+  host_only: Option<bool>,
+  files: Vec<PathBuf>,
+  file_opts: Vec<(PathBuf, FileOpts)>,
+  header_path: Option<PathBuf>,
+  intermediate_dir: Option<PathBuf>,
+  out_dir: Option<PathBuf>,
+  archiver: Option<PathBuf>,
+  ar_flags: Vec<String>,
+  thin_archive: bool,
+  static_crt: Option<bool>,
+  objects: Vec<PathBuf>,
+  cargo_metadata: bool,
+  hide_symbols: bool,
+  symbol_prefix: Option<String>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  variants: Vec<(String, Callbacks<VariantFn>)>,
+  variant: Option<String>,
+  specializations: Vec<(String, Vec<String>)>,
+  rust_dispatch: bool,
+  strip_debug: bool,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  post_process: Callbacks<PostProcessFn>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  before_compile: Callbacks<BeforeCompileFn>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  after_compile: Callbacks<AfterCompileFn>,
+  trace: bool,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  timings: Trace,
+  report_path: Option<PathBuf>,
+  text_size_budgets: Vec<(Target, u64)>,
+  timeout: Option<Duration>,
+  retries: u32,
+  jobs: Option<usize>,
+  skip_if_unavailable: bool,
+  pregenerated_bindings: Option<PathBuf>,
+  allow_missing_compiler: bool,
+  verify_determinism: bool,
+  compile_commands: Option<PathBuf>,
+  flags: Vec<String>,
+  flags_if_supported: Vec<String>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  help: Cache<String>,
+  includes: Vec<PathBuf>,
+  incremental: bool,
+  instrument: bool,
+  opt_level: Option<u32>,
+  opt_level_for_size: u32,
+  opt_level_debug: Option<u32>,
+  opt_level_release: Option<u32>,
+  opts: Vec<OptFlag>,
+  pic: Option<Pic>,
+  targets: Option<Vec<Target>>,
+  vectorcall: bool,
+  target_os: Option<TargetOs>,
+  target_cpus: Vec<(Target, Cpu)>,
+  triple_rules: Vec<TripleRule>,
+  filter_unsupported: bool,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  supported_targets: Cache<Vec<Target>>,
+  #[cfg_attr(feature = "serde", serde(skip))]
+  version: Cache<String>,
+  verbosity: Option<Verbosity>,
+  werror: bool,
+  werror_perf: bool,
+  warning_filters: Vec<Warning>,
+  required_versions: Vec<String>,
+  warnings: bool,
+  wperf: bool,
+}
+
+impl Config {
+  /// Constructs a new instance of a blank set of configuration.
   ///
-  /// Default value: `true`
-  pub fn enable_loop_unroll(&mut self, val: bool) -> &mut Self {
-    self.loop_unroll = val;
+  /// The builder is finished with the `compile` function.
+  pub fn new() -> Config {
+    Config {
+      addressing: Addr::Auto,
+      architecture: None,
+      code_model: None,
+      color: ColorChoice::Auto,
+      cpu: None,
+      custom_suffixes: vec![],
+      cxx_include_file: None,
+      definitions: vec![],
+      target_definitions: vec![],
+      force_alignment: None,
+      debug: None,
+      path_remaps: vec![],
+      dwarf_version: None,
+      dll_export: None,
+      emit_header: true,
+      emit_spirv: false,
+      emit_llvm: LlvmOutput::None,
+      emit_assembly: false,
+      asm_syntax: None,
+      envs: vec![],
+      current_dir: None,
+      math_lib: Math::Default,
+      svml_path: None,
+      sysroot: None,
+      native: false,
+      no_stdlib: false,
+      no_cpp: false,
+      no_omit_frame_pointer: false,
+      shared_cache: None,
+      cache_backend: Backend(None),
+      features: false,
+      host_only: None,
+      files: vec![],
+      file_opts: vec![],
+      header_path: None,
+      intermediate_dir: None,
+      out_dir: None,
+      archiver: None,
+      ar_flags: vec![],
+      thin_archive: false,
+      static_crt: None,
+      objects: vec![],
+      cargo_metadata: true,
+      hide_symbols: false,
+      symbol_prefix: None,
+      variants: vec![],
+      variant: None,
+      specializations: vec![],
+      rust_dispatch: false,
+      strip_debug: false,
+      post_process: Callbacks::default(),
+      before_compile: Callbacks::default(),
+      after_compile: Callbacks::default(),
+      trace: false,
+      report_path: None,
+      text_size_budgets: vec![],
+      timeout: None,
+      retries: 0,
+      jobs: None,
+      skip_if_unavailable: false,
+      pregenerated_bindings: None,
+      allow_missing_compiler: false,
+      verify_determinism: false,
+      compile_commands: None,
+      timings: Trace::default(),
+      flags: vec![],
+      flags_if_supported: vec![],
+      help: Cache::new(),
+      includes: vec![],
+      incremental: true,
+      instrument: false,
+      opt_level: None,
+      opt_level_for_size: 1,
+      opt_level_debug: None,
+      opt_level_release: None,
+      opts: Vec::new(),
+      pic: None,
+      targets: None,
+      vectorcall: false,
+      target_os: None,
+      target_cpus: vec![],
+      triple_rules: vec![],
+      filter_unsupported: false,
+      supported_targets: Cache::new(),
+      version: Cache::new(),
+      verbosity: None,
+      werror: true,
+      werror_perf: false,
+      warning_filters: vec![],
+      required_versions: vec![],
+      warnings: true,
+      wperf: true,
+    }
+  }
+
+  /// Sets the addressing mode of the compiled ispc code.
+  ///
+  /// By default, ispc-generated code is addressed with `i32`s on 32-bit targets
+  /// and `i64`s on 64-bit ones. If your arrays have more than 2 billion
+  /// elements, you'll need 64-bit addressing, and if your arrays are always
+  /// small, `Addr::A32` may be faster on 64-bit targets.
+  ///
+  /// Default value: `Addr::Auto`
+  pub fn addressing(&mut self, a: Addr) -> &mut Self {
+    self.addressing = a;
     self
   }
 
-  /// Enables or disables faster masked vector loads on SSE. This may cause reads
-  /// to go off the end of arrays.
+  /// Sets the code model of the compiled ispc code.
   ///
-  /// Default value: `false`
-  pub fn enable_fast_masked_vload(&mut self, val: bool) -> &mut Self {
-    self.fast_masked_vload = val;
+  /// Default value: ispc's default, which is `CodeModel::Small`
+  pub fn code_model(&mut self, m: CodeModel) -> &mut Self {
+    self.code_model = Some(m);
     self
   }
 
-  /// Enables or disables non-IEEE-754 compliant math operations.
+  /// Selects whether ispc's warnings and errors are colored.
   ///
-  /// Enabling this option may cause code to go much faster, but have
-  /// unpredictable error in the result, and corner cases such as `inf` and `NaN`
-  /// may not be handled properly.
+  /// With `ColorChoice::Auto`, they are colored if `CARGO_TERM_COLOR` is
+  /// `always`. Cargo captures the output of build scripts, so otherwise they
+  /// are only colored when run by hand, with stderr a terminal, `TERM` not
+  /// `dumb` and `NO_COLOR` unset.
   ///
-  /// Default value: `false`
-  pub fn enable_fast_math(&mut self, val: bool) -> &mut Self {
-    self.fast_math = val;
+  /// Default value: `ColorChoice::Auto`
+  pub fn color(&mut self, c: ColorChoice) -> &mut Self {
+    self.color = c;
     self
   }
 
-  /// Enables or disables the generation of aligned vector load and store
-  /// instructions.
+  /// Adds an entry to the target CPU set.
   ///
-  /// Enabling this option may make code run faster, but it will result in
-  /// undefined behavior if buffers are not aligned correctly.
+  /// Code will be generated for all CPUs in the target CPU set, and the correct
+  /// version to run will be autodetected at run time.
   ///
-  /// Default value: `false`
-  pub fn force_aligned_memory(&mut self, val: bool) -> &mut Self {
-    self.force_aligned_memory = val;
+  /// By default, the target CPU set is empty, as it can be automaatically
+  /// determined by the selected `target`s.
+  ///
+  /// Default value: `[]`
+  pub fn cpu(&mut self, c: Cpu) -> &mut Self {
+    if self.cpu.is_none() { self.cpu = Some(vec![]); }
+    self.cpu.as_mut().map(|cs| cs.push(c));
     self
   }
 
-  /// Enables or disables the generation of position-independent code.
+  /// Adds several CPUs, as `cpu` does.
+  pub fn cpus<I: IntoIterator<Item=Cpu>>(&mut self, cs: I) -> &mut Self {
+    for c in cs { self.cpu(c); }
+    self
+  }
+
+  /// Sets the header of intrinsics that C++ emitted for a `Generic_*` target
+  /// is compiled against.
   ///
-  /// This should generally be `true`, unless you have a really good reason
-  /// otherwise.
+  /// ispc ships a selection of these in its `examples/intrinsics` directory,
+  /// such as `generic-16.h` or `sse4.h`. The directory containing the header
+  /// is also added to the include path of the C++ compiler.
   ///
-  /// Default value: `true` on x86_64, `false` on x86.
-  pub fn pic(&mut self, val: bool) -> &mut Self {
-    self.pic = Some(val);
+  /// Default value: none
+  pub fn cxx_include_file<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.cxx_include_file = Some(p.as_ref().to_path_buf());
     self
   }
 
-  /// Adds a target to generate code for.
+  /// Tunes the generated code for the machine running the build, similar to
+  /// `-march=native`.
   ///
-  /// The first target that's added replaces the default list. Every subsequent
-  /// target will be added to this new list of targets. Only one target per
-  /// ISA may be selected. For example, you may have the simultaneous choises of
-  /// `[ Sse2_i32x4, Sse4_i32x8 ]`, but not `[ Sse2_i32x4, Sse2_i32x8 ]`.
+  /// The best matching CPU and a single target are selected by inspecting the
+  /// build host's CPU, so there is no multi-ISA dispatch. Only use this when the
+  /// code is never going to run anywhere else. Any explicitly added `cpu` or
+  /// `target` takes precedence. This cannot be used when cross compiling.
+  pub fn cpu_native(&mut self) -> &mut Self {
+    self.native = true;
+    self
+  }
+
+  /// Derives the default target list from the target features Rust is compiled
+  /// with, such as `RUSTFLAGS="-C target-feature=+avx2"`.
   ///
-  /// Default value: `[ Sse2, Sse4, Avx1, Avx1_1, Avx2 ]`
-  pub fn target(&mut self, t: Target) -> &mut Self {
-    if self.targets.is_none() { self.targets = Some(vec![]); }
-    self.targets.as_mut().map(|ts| ts.push(t));
+  /// Since the Rust code may already assume those features are present, any
+  /// default target for a less capable ISA is dropped. With `+avx2`, for
+  /// example, only `Avx2` is built. Explicitly added targets are unaffected.
+  ///
+  /// Default value: `false`
+  pub fn targets_from_features(&mut self, val: bool) -> &mut Self {
+    self.features = val;
     self
   }
 
-  /// Force all warnings as errors.
-  ///
-  /// If enabled, warnings will break the build. If there are warnings, not being
-  /// treated as errors, cargo will currently silently eat them. Sorry.
-  ///
-  /// Bikeshed about it [here](https://github.com/rust-lang/cargo/issues/1106).
-  ///
-  /// Default value: `true`
-  pub fn werror(&mut self, val: bool) -> &mut Self {
-    self.werror = val;
-    self
+  /// Only builds a single target in debug profiles, to speed up development.
+  ///
+  /// The most capable of the selected targets that the build machine can run is
+  /// kept, and the rest are skipped. Release profiles and cross compilation
+  /// always build every target. This may also be turned on by setting the
+  /// `RISPC_HOST_ONLY` environment variable to `1`.
+  ///
+  /// Default value: `false`
+  pub fn host_isa_only(&mut self, val: bool) -> &mut Self {
+    self.host_only = Some(val);
+    self
+  }
+
+  /// Specifies a `-D` variable with an optional value.
+  ///
+  /// Default value: `[]`
+  pub fn define(&mut self, k: &str, v: Option<&str>) -> &mut Self {
+    self.definitions.push((k.into(), v.map(|v| v.into())));
+    self
+  }
+
+  /// Specifies several `-D` variables, as `define` does.
+  pub fn defines<I, K, V>(&mut self, defs: I) -> &mut Self
+    where I: IntoIterator<Item=(K, Option<V>)>, K: AsRef<str>, V: AsRef<str> {
+    for (k, v) in defs { self.define(k.as_ref(), v.as_ref().map(|v| v.as_ref())); }
+    self
+  }
+
+  /// Specifies a `-D` variable which is only defined when compiling for
+  /// target `t`, such as a different `WIDTH` for each ISA.
+  ///
+  /// ispc compiles every target of a multi-target build with the same
+  /// definitions, so when targets need different ones each file is compiled
+  /// for every target with the first's definitions, then again for each of the
+  /// others alone. For MSVC, whose objects can't be renamed, each of those runs
+  /// compiles every target, which multiplies the build time by their number.
+  ///
+  /// Default value: `[]`
+  pub fn define_for_target(&mut self, t: Target, k: &str, v: Option<&str>) -> &mut Self {
+    self.target_definitions.push((t, k.into(), v.map(|v| v.into())));
+    self
+  }
+
+  /// Turns on or off generation of debug info.
+  ///
+  /// This will generally be automatically determined by the currently selected
+  /// cargo profile.
+  pub fn debug(&mut self, val: bool) -> &mut Self {
+    self.debug = Some(val);
+    self
+  }
+
+  /// Rewrites paths beginning with `from` in the debug info to begin with `to`
+  /// instead, like rustc's `--remap-path-prefix`, so that it doesn't depend on
+  /// where the build happened.
+  ///
+  /// ispc has no flag for this, so the objects it writes are patched, which
+  /// needs `to` to be no longer than `from`; the build fails with
+  /// `Error::InvalidConfig` otherwise. The C++ of `Generic_*` targets is
+  /// compiled with `-fdebug-prefix-map`, where supported.
+  ///
+  /// Default value: `[]`
+  pub fn remap_path_prefix<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, from: P, to: Q) -> &mut Self {
+    self.path_remaps.push((from.as_ref().to_path_buf(), to.as_ref().to_path_buf()));
+    self
+  }
+
+  /// Selects the DWARF version (2 through 5) of the debug info, for debuggers
+  /// and tools that can't read the version LLVM picks by default. Has no effect
+  /// unless debug info is generated.
+  ///
+  /// Default value: chosen by ispc
+  pub fn dwarf_version(&mut self, version: u32) -> &mut Self {
+    self.dwarf_version = Some(version);
+    self
+  }
+
+  /// Selects how much the build script prints. The `RISPC_VERBOSE` environment
+  /// variable overrides this, and may be set to `0`, `1` or `2`, or the name of
+  /// a level in lowercase.
+  ///
+  /// Default value: `Verbosity::Normal`
+  pub fn verbosity(&mut self, v: Verbosity) -> &mut Self {
+    self.verbosity = Some(v);
+    self
+  }
+
+  /// Marks exported functions as `__declspec(dllexport)`, so that they are
+  /// visible from a `cdylib` built on Windows.
+  ///
+  /// Default value: `true` on `*-windows-msvc` targets, `false` otherwise.
+  pub fn dll_export(&mut self, val: bool) -> &mut Self {
+    self.dll_export = Some(val);
+    self
+  }
+
+  /// Turns on or off generation of C headers for the exported ispc functions.
+  ///
+  /// A header is written for every file, and a header including all of them is
+  /// written to `header_path`. Rust bindings are generated from it, so they are
+  /// not generated when this is turned off.
+  ///
+  /// Default value: `true`
+  pub fn emit_header(&mut self, val: bool) -> &mut Self {
+    self.emit_header = val;
+    self
+  }
+
+  /// Sets where the header including the headers of all files is written.
+  ///
+  /// A relative path is relative to `OUT_DIR`.
+  ///
+  /// Default value: `OUT_DIR/<libname>.h`, where `<libname>` is the name of the
+  /// library given to `compile`.
+  pub fn header_path<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.header_path = Some(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Sets where objects, dependency files, the headers of each file, and
+  /// everything else ispc writes out are kept.
+  ///
+  /// A relative path is relative to `OUT_DIR`. Libraries compiled from the same
+  /// build script should each have their own.
+  ///
+  /// Default value: `OUT_DIR/rispc/<libname>/`, where `<libname>` is the name of
+  /// the library given to `compile`.
+  pub fn intermediate_dir<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.intermediate_dir = Some(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Sets the directory the library, headers and bindings are written to, for
+  /// use outside of a build script. Wherever `OUT_DIR` is mentioned, this is
+  /// meant instead.
+  ///
+  /// Default value: `$OUT_DIR`, which Cargo sets for build scripts.
+  pub fn out_dir<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.out_dir = Some(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Sets the archiver used to build the static library.
+  ///
+  /// Default value: the `AR_<target>`, `TARGET_AR` or `AR` environment
+  /// variable, or else `ar`, `lib.exe` on MSVC, or `llvm-ar` for wasm.
+  pub fn archiver<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.archiver = Some(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Adds an arbitrary argument to the invocation of the archiver, before the
+  /// archive and objects.
+  ///
+  /// Default value: `[]`
+  pub fn ar_flag(&mut self, f: &str) -> &mut Self {
+    self.ar_flags.push(f.into());
+    self
+  }
+
+  /// Builds a thin archive, which refers to the objects where they are instead
+  /// of holding a copy of them. Large multi-target libraries are much quicker
+  /// to archive this way, but can't be moved away from their objects.
+  ///
+  /// This needs GNU `ar` or `llvm-ar`, and is ignored on MSVC.
+  ///
+  /// Default value: `false`
+  pub fn thin_archive(&mut self, val: bool) -> &mut Self {
+    self.thin_archive = val;
+    self
+  }
+
+  /// Sets whether the C++ emitted for `Generic_*` targets, and shared libraries,
+  /// are built against the static C runtime on MSVC, passing `/MT` rather than
+  /// `/MD`. Rust code must use the same one to link with them, so a warning is
+  /// printed when this differs from what rustc was configured with.
+  ///
+  /// Default value: whether the `crt-static` target feature is enabled, as it
+  /// is with `-C target-feature=+crt-static`
+  pub fn static_crt(&mut self, val: bool) -> &mut Self {
+    self.static_crt = Some(val);
+    self
+  }
+
+  /// Adds an object file, compiled some other way, to the static or shared
+  /// library. It is left alone by `symbol_prefix`, `strip_debug` and
+  /// `post_process`.
+  ///
+  /// Default value: `[]`
+  pub fn object<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.objects.push(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Sets whether the `cargo:rustc-link-*` lines linking the static library,
+  /// and the libraries it needs, are printed. Without them, the build script
+  /// has to link it itself, such as when it is packaged up with others.
+  ///
+  /// Default value: `true`
+  pub fn cargo_metadata(&mut self, val: bool) -> &mut Self {
+    self.cargo_metadata = val;
+    self
+  }
+
+  /// Makes the symbols of the objects local, except for the functions declared
+  /// `export` in ispc and their variants for each target, so that a `cdylib`
+  /// linking the library doesn't export ispc's internals.
+  ///
+  /// This runs `objcopy`, or whatever the `OBJCOPY` environment variable names,
+  /// on every object. It has no effect on MSVC, where nothing is exported
+  /// unless `dll_export` says so, or on `compile_shared`, which always does
+  /// this.
+  ///
+  /// Default value: `false`
+  pub fn hide_internal_symbols(&mut self, val: bool) -> &mut Self {
+    self.hide_symbols = val;
+    self
+  }
+
+  /// Prefixes the names of the functions declared `export` in ispc, such as
+  /// `mycrate_transform` for `transform`, so that they can't clash with those
+  /// of another library. The headers and Rust bindings use the new names.
+  ///
+  /// Like `hide_internal_symbols`, this runs `objcopy` on every object.
+  ///
+  /// Default value: `None`
+  pub fn symbol_prefix(&mut self, prefix: &str) -> &mut Self {
+    self.symbol_prefix = Some(prefix.into());
+    self
+  }
+
+  /// Strips the debug info from the objects before they are archived or
+  /// linked, with `objcopy --strip-debug`, as for `hide_internal_symbols`.
+  ///
+  /// Default value: `false`
+  pub fn strip_debug(&mut self, val: bool) -> &mut Self {
+    self.strip_debug = val;
+    self
+  }
+
+  /// Runs `f` on the objects before they are archived or linked, after
+  /// `strip_debug`, such as to sign them or run some other tool on them. An
+  /// error from `f` fails the build. Several may be added, which run in order.
+  ///
+  /// They run on every build, including on objects which ispc didn't need to
+  /// compile again, and so should do nothing to objects they already handled.
+  ///
+  /// Default value: none
+  pub fn post_process<F>(&mut self, f: F) -> &mut Self
+    where F: Fn(&[PathBuf]) -> io::Result<()> + Send + Sync + 'static {
+    self.post_process.0.push(Arc::new(f));
+    self
+  }
+
+  /// Runs `f` on each source file and the command line about to compile it,
+  /// such as to add flags for that file alone. It runs before any other ispc
+  /// command for the file, including those `emit_llvm` and `emit_assembly`
+  /// add, and whatever it adds decides whether the last build can be reused.
+  /// Several may be added, which run in order, possibly for several files at
+  /// once.
+  ///
+  /// Default value: none
+  pub fn before_compile<F>(&mut self, f: F) -> &mut Self
+    where F: Fn(&Path, &mut Tool) + Send + Sync + 'static {
+    self.before_compile.0.push(Arc::new(f));
+    self
+  }
+
+  /// Runs `f` on each source file and what compiling it produced, before
+  /// `symbol_prefix`, `strip_debug` and `post_process` handle the objects of
+  /// all files together. It runs even if ispc didn't need to compile the file
+  /// again. Several may be added, which run in order, possibly for several
+  /// files at once.
+  ///
+  /// Default value: none
+  pub fn after_compile<F>(&mut self, f: F) -> &mut Self
+    where F: Fn(&Path, &CompiledObject) + Send + Sync + 'static {
+    self.after_compile.0.push(Arc::new(f));
+    self
+  }
+
+  /// Also builds the same sources as another library, `<output>_<name>`, with
+  /// whatever `f` changes, such as a definition. Its objects go in their own
+  /// directory, and its exported functions, its header and its Rust bindings
+  /// are suffixed with `_<name>`, so that both libraries can be linked into
+  /// the same crate. The rest of what it defines is made local, as
+  /// `hide_internal_symbols` does. What was built is in
+  /// `CompileOutput::variant`.
+  ///
+  /// `f` runs on a copy of this `Config` when compiling, and so sees all of
+  /// its settings. Several variants may be added.
+  ///
+  /// Default value: none
+  pub fn variant<F>(&mut self, name: &str, f: F) -> &mut Self
+    where F: Fn(&mut Config) + Send + Sync + 'static {
+    self.variants.push((name.into(), Callbacks(vec![Arc::new(f)])));
+    self
+  }
+
+  /// Compiles the sources once for each of `values` of the definition `name`,
+  /// all into the same library. The exported functions of each are suffixed
+  /// with the first word of `name` and the value, such as `kernel_tile8` for
+  /// `specialize("tile_size", &["8", "16"])`, and the header and Rust bindings
+  /// declare all of them. The rest of what each defines is made local, as
+  /// `hide_internal_symbols` does.
+  ///
+  /// Several definitions may be specialized, in which case every combination
+  /// of their values is compiled, such as to `kernel_tile8_unroll2`.
+  ///
+  /// Default value: none
+  pub fn specialize(&mut self, name: &str, values: &[&str]) -> &mut Self {
+    self.specializations.push((name.into(), values.iter().map(|&v| v.into()).collect()));
+    self
+  }
+
+  /// Compiles each target on its own, rather than leaving ispc to dispatch
+  /// between them, and dispatches from Rust instead. The exported functions
+  /// for each target are suffixed with it, such as `kernel_avx2`, and the
+  /// bindings gain functions of the unsuffixed names, which call that of the
+  /// most capable target the CPU supports, as `is_x86_feature_detected!`
+  /// finds.
+  ///
+  /// The bindings also list the targets, most capable first, in
+  /// `ISPC_TARGETS`, and `ispc_select_target("avx2")` makes the functions
+  /// dispatch to another supported one, such as to avoid AVX-512 on some
+  /// CPUs. Only the x86 targets other than `Avx512knl_i32x16` may be
+  /// selected, and bindings are needed, so this doesn't work with
+  /// `compile_shared` or without `emit_header`.
+  ///
+  /// Default value: `false`
+  pub fn rust_dispatch(&mut self, val: bool) -> &mut Self {
+    self.rust_dispatch = val;
+    self
+  }
+
+  /// Generates SPIR-V for Intel GPUs instead of a static library of x86 code.
+  ///
+  /// Each file is compiled to a `.spv` module in `intermediate_dir`, and no
+  /// archive or bindings are generated. The location of every module is
+  /// exported to the crate being built as an environment variable named
+  /// `<LIBNAME>_<FILESTEM>_SPV`, so that it may be embedded for the runtime to
+  /// load. Files sharing a stem are told apart by their whole path instead,
+  /// as in `<LIBNAME>_SRC_A_KERNEL_SPV` for `src/a/kernel.ispc`:
+  ///
+  /// ```ignore
+  /// static KERNEL: &'static [u8] = include_bytes!(env!("MANDEL_MANDEL_SPV"));
+  /// ```
+  ///
+  /// Default value: `false`
+  pub fn emit_spirv(&mut self, val: bool) -> &mut Self {
+    self.emit_spirv = val;
+    self
+  }
+
+  /// Writes out the LLVM IR of every object file next to it in
+  /// `intermediate_dir`, such as `foo.bc` and `foo_avx2.bc` for `foo.o` and
+  /// `foo_avx2.o`.
+  ///
+  /// This requires invoking ispc a second time for each file, with the same
+  /// flags. It has no effect on `Generic_*` targets, or when emitting SPIR-V.
+  ///
+  /// Default value: `LlvmOutput::None`
+  pub fn emit_llvm(&mut self, o: LlvmOutput) -> &mut Self {
+    self.emit_llvm = o;
+    self
+  }
+
+  /// Writes out the assembly of every object file next to it in
+  /// `intermediate_dir`, as `.s` files named like the LLVM IR of `emit_llvm`.
+  ///
+  /// Like `emit_llvm`, this invokes ispc again for each file.
+  ///
+  /// Default value: `false`
+  pub fn emit_assembly(&mut self, val: bool) -> &mut Self {
+    self.emit_assembly = val;
+    self
+  }
+
+  /// Selects the syntax of the x86 assembly written by `emit_assembly`. Other
+  /// architectures only have one syntax, and ignore this.
+  ///
+  /// Default value: chosen by ispc, which uses AT&T
+  pub fn asm_syntax(&mut self, syntax: AsmSyntax) -> &mut Self {
+    self.asm_syntax = Some(syntax);
+    self
+  }
+
+  /// Selects the math library to call out to.
+  ///
+  /// `Math::System` and `Math::Svml` are linked in automatically. See
+  /// `svml_path` for where SVML is searched for.
+  ///
+  /// Default value: `Math::Default`
+  pub fn math_lib(&mut self, m: Math) -> &mut Self {
+    self.math_lib = m;
+    self
+  }
+
+  /// Sets the directory containing the SVML library, such as the `lib`
+  /// directory of an Intel compiler or oneAPI installation.
+  ///
+  /// Default value: the linker's default search path
+  pub fn svml_path<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.svml_path = Some(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Compiles without ispc's standard library, for freestanding environments.
+  ///
+  /// Default value: `false`
+  pub fn no_stdlib(&mut self, val: bool) -> &mut Self {
+    self.no_stdlib = val;
+    self
+  }
+
+  /// Compiles sources verbatim, without running them through the C
+  /// preprocessor. `#include` and `-D` definitions have no effect.
+  ///
+  /// Default value: `false`
+  pub fn no_cpp(&mut self, val: bool) -> &mut Self {
+    self.no_cpp = val;
+    self
+  }
+
+  /// Keeps the frame pointer in all generated functions, so that profilers such
+  /// as `perf` or VTune can walk the stack through ispc code.
+  ///
+  /// Default value: `false`
+  pub fn no_omit_frame_pointer(&mut self, val: bool) -> &mut Self {
+    self.no_omit_frame_pointer = val;
+    self
+  }
+
+  /// Emits calls to `ISPCInstrument` throughout the generated code, reporting
+  /// the source location and the current execution mask.
+  ///
+  /// `rispcrt` provides that symbol; register a closure with
+  /// `rispcrt::set_instrument_hook` to receive the events.
+  ///
+  /// Default value: `false`
+  pub fn instrument(&mut self, val: bool) -> &mut Self {
+    self.instrument = val;
+    self
+  }
+
+  /// Adds a file to the set of files to be compiled together.
+  ///
+  /// Files may share a name if they are in different directories, as in
+  /// `src/a/kernel.ispc` and `src/b/kernel.ispc`, and may be outside the crate,
+  /// as in `../shared/kernel.ispc`.
+  ///
+  /// Default value: `[]`
+  pub fn file<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.files.push(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Adds several files to the set of files to be compiled together.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// use std::fs;
+  ///
+  /// let kernels = fs::read_dir("src/kernels").unwrap()
+  ///   .map(|e| e.unwrap().path())
+  ///   .filter(|p| p.extension().map_or(false, |e| e == "ispc"));
+  ///
+  /// rispc::Config::new()
+  ///   .files(kernels)
+  ///   .compile("kernels");
+  /// ```
+  pub fn files<I>(&mut self, ps: I) -> &mut Self
+    where I: IntoIterator, I::Item: AsRef<Path> {
+    for p in ps { self.file(p); }
+    self
+  }
+
+  /// Adds a file to be compiled with some settings overridden, such as extra
+  /// definitions or a different math library.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// rispc::Config::new()
+  ///   .file("src/strict.ispc")
+  ///   .file_with("src/fast.ispc", |f| {
+  ///     f.opt(rispc::OptFlag::Fast_math, true)
+  ///      .define("APPROX_RCP", None);
+  ///   })
+  ///   .compile("kernels");
+  /// ```
+  pub fn file_with<P, F>(&mut self, p: P, f: F) -> &mut Self
+    where P: AsRef<Path>, F: FnOnce(&mut FileOpts) {
+    let mut opts = FileOpts { definitions: vec![], opts: vec![], math_lib: None, targets: None };
+    f(&mut opts);
+    self.file_opts.push((p.as_ref().to_path_buf(), opts));
+    self.file(p)
+  }
+
+  /// Adds an arbitrary argument to every invocation of ispc.
+  ///
+  /// Whitespace-separated flags from the `RISPC_FLAGS` environment variable
+  /// are added after these, followed by those from `RISPC_FLAGS_<target>`,
+  /// where `<target>` is the Rust target triple, optionally with `-` replaced
+  /// by `_`.
+  ///
+  /// Default value: `[]`
+  pub fn flag(&mut self, f: &str) -> &mut Self {
+    self.flags.push(f.into());
+    self
+  }
+
+  /// Adds an arbitrary argument to every invocation of ispc, if the installed
+  /// version of ispc supports it.
+  ///
+  /// A flag is considered supported if its name, and its value if it has one,
+  /// are mentioned by `ispc --help`. Unsupported flags are silently dropped.
+  ///
+  /// Default value: `[]`
+  pub fn flag_if_supported(&mut self, f: &str) -> &mut Self {
+    self.flags_if_supported.push(f.into());
+    self
+  }
+
+  /// Adds a directory to the `#include` search path.
+  ///
+  /// Changes to anything within these directories will cause the build script
+  /// to be re-run.
+  ///
+  /// Default value: `[]`
+  pub fn include<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.includes.push(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Adds several directories to the `#include` search path, as `include`
+  /// does.
+  pub fn includes<I>(&mut self, ps: I) -> &mut Self
+    where I: IntoIterator, I::Item: AsRef<Path> {
+    for p in ps { self.include(p); }
+    self
+  }
+
+  /// Sets an environment variable for every invocation of ispc, without
+  /// changing the environment of the build script itself.
+  ///
+  /// Default value: `[]`
+  pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, k: K, v: V) -> &mut Self {
+    self.envs.push((k.as_ref().to_owned(), v.as_ref().to_owned()));
+    self
+  }
+
+  /// Runs ispc from `dir`, which determines how relative `#include`s are
+  /// searched for and the file names recorded in debug info and `__FILE__`.
+  ///
+  /// Source files and include directories are still given relative to the
+  /// build script's working directory, as usual.
+  ///
+  /// Default value: the build script's working directory
+  pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+    self.current_dir = Some(dir.as_ref().to_path_buf());
+    self
+  }
+
+  /// Set the optimization level.
+  ///
+  /// The `ISPC_OPT_LEVEL` environment variable overrides this, and every other
+  /// way of choosing the optimization level.
+  ///
+  /// Default value: inferred from current cargo profile
+  pub fn opt_level(&mut self, level: u32) -> &mut Self {
+    self.opt_level = Some(level);
+    self
+  }
+
+  /// Sets the optimization level used for cargo profiles which optimize for
+  /// size, with `opt-level = "s"` or `"z"`. ispc has no such levels itself.
+  ///
+  /// Default value: `1`
+  pub fn opt_level_for_size(&mut self, level: u32) -> &mut Self {
+    self.opt_level_for_size = level;
+    self
+  }
+
+  /// Sets the optimization level used in the `debug` profile, regardless of
+  /// its `opt-level`. Unoptimized ispc code is often too slow to be usable even
+  /// while developing.
+  ///
+  /// Default value: the profile's `opt-level`
+  pub fn opt_level_debug(&mut self, level: u32) -> &mut Self {
+    self.opt_level_debug = Some(level);
+    self
+  }
+
+  /// Sets the optimization level used in the `release` profile, regardless of
+  /// its `opt-level`.
+  ///
+  /// Default value: the profile's `opt-level`
+  pub fn opt_level_release(&mut self, level: u32) -> &mut Self {
+    self.opt_level_release = Some(level);
+    self
+  }
+
+  /// Turns a single ispc optimization switch on or off. The `enable_*`
+  /// methods below are shorthands for the common ones.
+  ///
+  /// Default value: all off
+  pub fn opt(&mut self, flag: OptFlag, val: bool) -> &mut Self {
+    self.opts.retain(|&f| f != flag);
+    if val { self.opts.push(flag); }
+    self
+  }
+
+  /// Enables or disables asssertations in the code.
+  ///
+  /// Default value: `true`
+  pub fn enable_assertations(&mut self, val: bool) -> &mut Self {
+    self.opt(OptFlag::Disable_assertions, !val)
+  }
+
+  /// Enables or disables generation of fused multiply-add instructions.
+  ///
+  /// Default value: `true`
+  pub fn enable_fma(&mut self, val: bool) -> &mut Self {
+    self.opt(OptFlag::Disable_fma, !val)
+  }
+
+  /// Enables or disables loop unrolling.
+  ///
+  /// Default value: `true`
+  pub fn enable_loop_unroll(&mut self, val: bool) -> &mut Self {
+    self.opt(OptFlag::Disable_loop_unroll, !val)
+  }
+
+  /// Enables or disables faster masked vector loads on SSE. This may cause reads
+  /// to go off the end of arrays.
+  ///
+  /// Default value: `false`
+  pub fn enable_fast_masked_vload(&mut self, val: bool) -> &mut Self {
+    self.opt(OptFlag::Fast_masked_vload, val)
+  }
+
+  /// Enables or disables non-IEEE-754 compliant math operations.
+  ///
+  /// Enabling this option may cause code to go much faster, but have
+  /// unpredictable error in the result, and corner cases such as `inf` and `NaN`
+  /// may not be handled properly.
+  ///
+  /// Default value: `false`
+  pub fn enable_fast_math(&mut self, val: bool) -> &mut Self {
+    self.opt(OptFlag::Fast_math, val)
+  }
+
+  /// Enables or disables the generation of aligned vector load and store
+  /// instructions.
+  ///
+  /// Enabling this option may make code run faster, but it will result in
+  /// undefined behavior if buffers are not aligned correctly.
+  ///
+  /// Default value: `false`
+  pub fn force_aligned_memory(&mut self, val: bool) -> &mut Self {
+    self.opt(OptFlag::Force_aligned_memory, val)
+  }
+
+  /// Sets the alignment, in bytes, of memory allocated with `new` in ispc
+  /// code.
+  ///
+  /// Default value: chosen by ispc from the target's vector width
+  pub fn force_alignment(&mut self, align: u32) -> &mut Self {
+    self.force_alignment = Some(align);
+    self
+  }
+
+  /// Undoes `force_alignment`, going back to ispc's default alignment.
+  pub fn clear_force_alignment(&mut self) -> &mut Self {
+    self.force_alignment = None;
+    self
+  }
+
+  /// Avoids using 512-bit `zmm` registers in code generated for AVX-512 targets,
+  /// preferring 256-bit vectors.
+  ///
+  /// Heavy use of `zmm` registers lowers the clock speed of the whole core on
+  /// many CPUs, which can cost more than the wider vectors gain.
+  ///
+  /// Default value: `false`
+  pub fn prefer_avx256(&mut self, val: bool) -> &mut Self {
+    self.opt(OptFlag::Disable_zmm, val)
+  }
+
+  /// Resets the flush-to-zero and denormals-are-zero floating point modes on
+  /// entry to exported functions, so denormals are handled as IEEE-754 requires
+  /// whatever mode the caller was running in.
+  ///
+  /// Default value: `false`
+  pub fn reset_ftz_daz(&mut self, val: bool) -> &mut Self {
+    self.opt(OptFlag::Reset_ftz_daz, val)
+  }
+
+  /// Enables or disables the generation of position-independent code.
+  ///
+  /// This should generally be `true`, unless you have a really good reason
+  /// otherwise.
+  ///
+  /// This is ignored when targeting wasm32, which has no notion of PIC.
+  ///
+  /// Default value: see `pic_mode`
+  pub fn pic(&mut self, val: bool) -> &mut Self {
+    self.pic_mode(if val { Pic::Pic } else { Pic::None })
+  }
+
+  /// Selects the kind of relocatable code to generate.
+  ///
+  /// Default value: `Pic::None` on Windows. Otherwise the relocation model rustc
+  /// builds with, if cargo reports one or `-C relocation-model` is among the
+  /// flags it passes rustc. Or else `Pic::None` on bare-metal targets, `Pic::Pie`
+  /// for static musl binaries, and `Pic::Pic` everywhere else.
+  pub fn pic_mode(&mut self, pic: Pic) -> &mut Self {
+    self.pic = Some(pic);
+    self
+  }
+
+  /// Adds a target to generate code for.
+  ///
+  /// The first target that's added replaces the default list. Every subsequent
+  /// target will be added to this new list of targets. Only one target per
+  /// ISA may be selected. For example, you may have the simultaneous choises of
+  /// `[ Sse2_i32x4, Sse4_i32x8 ]`, but not `[ Sse2_i32x4, Sse2_i32x8 ]`.
+  /// Adding the same target twice has no effect, and `compile` will fail if two
+  /// targets share an ISA.
+  ///
+  /// A `Generic_*` target makes ispc emit C++ instead of an object file, which
+  /// is then compiled with the host C++ compiler. It must be the only target.
+  ///
+  /// Default value: `[ Sse2, Sse4, Avx1, Avx1_1, Avx2 ]`, `[ Neon_i32x4 ]` when
+  /// targeting ARM, `[ Wasm_i32x4 ]` when targeting wasm32, or `[ Gen9_x8 ]`
+  /// when emitting SPIR-V.
+  pub fn target(&mut self, t: Target) -> &mut Self {
+    if self.targets.is_none() { self.targets = Some(vec![]); }
+    self.targets.as_mut().map(|ts| ts.push(t));
+    self
+  }
+
+  /// Adds several targets to generate code for, as `target` does.
+  pub fn targets<I: IntoIterator<Item=Target>>(&mut self, ts: I) -> &mut Self {
+    for t in ts { self.target(t); }
+    self
+  }
+
+  /// Sets the SDK whose system headers are searched by `#include`.
+  ///
+  /// When targeting iOS, this defaults to the `SDKROOT` environment variable set
+  /// by Xcode, so that kernels may be built by `xcodebuild` or `cargo-lipo`.
+  /// Its `usr/include` directory is added to the include path.
+  ///
+  /// Default value: none, or `$SDKROOT` when targeting iOS
+  pub fn sysroot<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.sysroot = Some(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Sets the operating system to generate code for.
+  ///
+  /// This is derived from Cargo's target triple, and only passed on to ispc as
+  /// `--target-os` when it differs from the build machine's, so that cross
+  /// compiled objects are in the format the Rust linker expects.
+  ///
+  /// Default value: inferred from the current cargo target
+  pub fn target_os(&mut self, os: TargetOs) -> &mut Self {
+    self.target_os = Some(os);
+    self
+  }
+
+  /// Adds a rule describing how to build for some set of target triples.
+  ///
+  /// Rules added here take precedence over the built-in ones, which cover the
+  /// triples Rust ships with. See `TripleRule` for details.
+  pub fn triple_rule(&mut self, rule: &TripleRule) -> &mut Self {
+    self.triple_rules.push(rule.clone());
+    self
+  }
+
+  /// Adds a target to generate code for, tuned for a specific CPU.
+  ///
+  /// This lets each variant of a multi-target build be tuned separately, such as
+  /// `Avx2` for `Core_avx2` alongside `Sse4` for `Slm`. Once any target is
+  /// paired with a CPU, every target must be, and the CPU set built up by
+  /// `cpu` is ignored.
+  pub fn target_with_cpu(&mut self, t: Target, c: Cpu) -> &mut Self {
+    self.target_cpus.retain(|&(ref pt, _)| *pt != t);
+    self.target_cpus.push((t.clone(), c));
+    self.target(t)
+  }
+
+  /// Adds a `Target::Custom` to generate code for, along with the suffix of the
+  /// object file ispc emits for it in a multi-target build.
+  ///
+  /// For example, `--target=avx512skx-x16` produces `foo_avx512skx.o`, so its
+  /// suffix is `avx512skx`. Targets added with `target(Target::Custom(..))`
+  /// guess the suffix as the part of the name before the first `-`.
+  pub fn custom_target(&mut self, name: &str, obj_suffix: &str) -> &mut Self {
+    self.custom_suffixes.push((name.into(), obj_suffix.into()));
+    self.target(Target::Custom(name.into()))
+  }
+
+  /// Drops targets that the installed ispc doesn't support, instead of failing.
+  ///
+  /// Before compiling, the requested targets are checked against the output of
+  /// `ispc --support-matrix`. If this is enabled, unsupported targets are
+  /// skipped with a warning. Otherwise, the build fails immediately, naming
+  /// them. Versions of ispc too old to have `--support-matrix` are not checked.
+  ///
+  /// Default value: `false`
+  pub fn filter_unsupported_targets(&mut self, val: bool) -> &mut Self {
+    self.filter_unsupported = val;
+    self
+  }
+
+  /// Uses the `vectorcall` calling convention for exported functions on
+  /// Windows, which passes vector arguments in registers.
+  ///
+  /// The generated bindings are declared `extern "vectorcall"` to match, which
+  /// requires `#![feature(abi_vectorcall)]` in the crate including them. This
+  /// has no effect on other operating systems.
+  ///
+  /// Default value: `false`
+  pub fn vectorcall(&mut self, val: bool) -> &mut Self {
+    self.vectorcall = val;
+    self
+  }
+
+  /// Force all warnings as errors.
+  ///
+  /// If enabled, warnings will break the build. Otherwise each is passed on to
+  /// cargo as a `cargo:warning`, once, however many targets or files it came
+  /// up for.
+  ///
+  /// Default value: `true`
+  pub fn werror(&mut self, val: bool) -> &mut Self {
+    self.werror = val;
+    self
+  }
+
+  /// Treats performance warnings as errors, which ispc's own `--werror` never
+  /// does. Useful to stop kernels regressing into gathers and scatters.
+  ///
+  /// Warnings disabled with `warn_perf` or `warning` don't count.
+  ///
+  /// Default value: `false`
+  pub fn werror_perf(&mut self, val: bool) -> &mut Self {
+    self.werror_perf = val;
+    self
+  }
+
+  /// Skips running ispc for a source file when neither it, nor anything it
+  /// `#include`s, nor the flags, nor the version of ispc have changed since the
+  /// objects already in `intermediate_dir` were compiled.
+  ///
+  /// Default value: `true`
+  pub fn incremental(&mut self, val: bool) -> &mut Self {
+    self.incremental = val;
+    self
+  }
+
+  /// Writes when each run of ispc started and how long it took to
+  /// `OUT_DIR/rispc_trace_<libname>.json`, for viewing in `chrome://tracing`
+  /// or Perfetto. The same timings are always in `CompileOutput::timings`.
+  ///
+  /// Default value: `false`
+  pub fn trace(&mut self, val: bool) -> &mut Self {
+    self.trace = val;
+    self
+  }
+
+  /// Writes a JSON summary of each successful build to `p`: the files compiled,
+  /// the targets and flags given to ispc, the size of each object, the warnings
+  /// printed, how long each file took and whether it was reused from the cache.
+  ///
+  /// A relative path is relative to `OUT_DIR`.
+  ///
+  /// Default value: None
+  pub fn report_path<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.report_path = Some(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Warns, with `cargo:warning`, when the code compiled for `t`, summed over
+  /// every file, is bigger than `bytes`. Any lane width of the same ISA counts.
+  /// The size for each target is printed with the rest of the build script's
+  /// output regardless, and is in the `report_path` and `CompileOutput`.
+  ///
+  /// Default value: no budget
+  pub fn text_size_budget(&mut self, t: Target, bytes: u64) -> &mut Self {
+    self.text_size_budgets.push((t, bytes));
+    self
+  }
+
+  /// Writes a `compile_commands.json` to `p`, listing every command ispc is
+  /// run with, for editors and language servers to understand the sources.
+  /// Commands whose outputs were reused are listed too. Libraries compiled
+  /// from the same build script may share one.
+  ///
+  /// A relative path is relative to `OUT_DIR`, so pass something like
+  /// `concat!(env!("CARGO_MANIFEST_DIR"), "/compile_commands.json")` to keep
+  /// it with the sources.
+  ///
+  /// Default value: None
+  pub fn compile_commands<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.compile_commands = Some(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Kills ispc if a single run of it takes longer than `timeout`, failing the
+  /// build with whatever it printed until then.
+  ///
+  /// Default value: None
+  pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  /// Runs ispc again, up to `n` more times, when it can't be started or its
+  /// output can't be read, such as on network filesystems which occasionally
+  /// fail. Compile errors, warnings which `werror` makes errors, and timeouts
+  /// aren't retried.
+  ///
+  /// Default value: `0`
+  pub fn retries(&mut self, n: u32) -> &mut Self {
+    self.retries = n;
+    self
+  }
+
+  /// Sets how many ispc processes are run at once, at most, across files and
+  /// targets.
+  ///
+  /// Default value: the number of jobs Cargo runs, from `NUM_JOBS`, or else
+  /// the number of CPUs.
+  pub fn jobs(&mut self, n: usize) -> &mut Self {
+    self.jobs = Some(n);
+    self
+  }
+
+  /// Skips compiling where ispc isn't expected to be installed, but the crate
+  /// is built anyway, such as to document it: on docs.rs, which sets `DOCS_RS`,
+  /// and wherever `RISPC_SKIP` is `1`, like `cargo check` in CI.
+  ///
+  /// Nothing is linked. Instead, `cargo:rustc-cfg=rispc_skipped` is printed, so
+  /// the crate can stub out the kernels with `#[cfg(rispc_skipped)]`, and the
+  /// `pregenerated_bindings` are copied to where the bindings would have been
+  /// generated. `compile_shared` returns where the library would have been.
+  ///
+  /// Default value: `false`
+  pub fn skip_if_unavailable(&mut self, val: bool) -> &mut Self {
+    self.skip_if_unavailable = val;
+    self
+  }
+
+  /// Sets the bindings to use when compiling is skipped, usually generated by
+  /// an earlier build and checked in. See `skip_if_unavailable`.
+  ///
+  /// Default value: None
+  pub fn pregenerated_bindings<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+    self.pregenerated_bindings = Some(p.as_ref().to_path_buf());
+    self
+  }
+
+  /// Builds nothing, rather than failing, when ispc can't be run, so the crate
+  /// can fall back to code of its own. `cargo:rustc-cfg=rispc_unavailable` is
+  /// printed for it to select that with `#[cfg(rispc_unavailable)]`, along with
+  /// a warning. Nothing is linked, no bindings are generated, and
+  /// `compile_shared` returns where the library would have been.
+  ///
+  /// Default value: `false`
+  pub fn allow_missing_compiler(&mut self, val: bool) -> &mut Self {
+    self.allow_missing_compiler = val;
+    self
+  }
+
+  /// Runs ispc twice on each file it compiles, failing the build if the
+  /// objects, headers or other outputs differ between the runs, such as due to
+  /// embedded timestamps or temporary file names. Files whose outputs are
+  /// reused from the last build or the shared cache aren't checked, so do this
+  /// with `incremental` disabled to check them all.
+  ///
+  /// Default value: `false`
+  pub fn verify_determinism(&mut self, val: bool) -> &mut Self {
+    self.verify_determinism = val;
+    self
+  }
+
+  /// Shares compiled objects between builds of any workspace on the machine,
+  /// through the directory named by `RISPC_CACHE_DIR`, or else `rispc` in the
+  /// user's cache directory. Nothing is ever removed from it. Setting
+  /// `RISPC_CACHE_DIR` to an empty string turns this off.
+  ///
+  /// Has no effect unless `incremental` is on.
+  ///
+  /// Default value: `true` if `RISPC_CACHE_DIR` is set, or a `cache_backend`
+  /// is given, or else `false`
+  pub fn shared_cache(&mut self, val: bool) -> &mut Self {
+    self.shared_cache = Some(val);
+    self
+  }
+
+  /// Keeps the shared cache somewhere other than a local directory, such as a
+  /// remote cache for a build farm.
+  ///
+  /// Default value: a `DirCache` in the directory described by `shared_cache`
+  pub fn cache_backend<B: CacheBackend + 'static>(&mut self, backend: B) -> &mut Self {
+    self.cache_backend = Backend(Some(Arc::new(backend)));
+    self
+  }
+
+  /// Enables or disables warnings.
+  ///
+  /// Default value: `true`
+  pub fn warn(&mut self, val: bool) -> &mut Self {
+    self.warnings = val;
+    self
+  }
+
+  /// Enables or disables warnings about suboptimal code performance.
+  ///
+  /// These will not turn into errors *even with `.werror(true)`*, so cargo will
+  /// silently ignore them anyhow. This is really suboptimal. Sorry.
+  ///
+  /// Bikeshed about it [here](https://github.com/rust-lang/cargo/issues/1106).
+  ///
+  /// Default value: `true`
+  pub fn warn_perf(&mut self, val: bool) -> &mut Self {
+    self.wperf = val;
+    self
+  }
+
+  /// Enables or disables a specific warning, such as performance warnings
+  /// about gathers.
+  ///
+  /// ispc has no switches for individual warnings, so disabled ones are
+  /// filtered out of its output instead. They are also never treated as
+  /// errors by `werror`.
+  ///
+  /// Default value: every warning is enabled
+  pub fn warning(&mut self, w: Warning, val: bool) -> &mut Self {
+    self.warning_filters.retain(|f| *f != w);
+    if !val { self.warning_filters.push(w); }
+    self
+  }
+
+  fn get_opt_level(&self) -> Result<u32, Error> {
+    let profile = match &*self.getenv_unwrap("PROFILE")? {
+      "debug"   => self.opt_level_debug,
+      "release" => self.opt_level_release,
+      _         => None,
+    };
+    let env = match self.getenv("ISPC_OPT_LEVEL") {
+      Some(l) => Some(l.parse().map_err(|_| {
+        Error::InvalidConfig(format!("ISPC_OPT_LEVEL must be a number, not `{}`", l))
+      })?),
+      None    => None,
+    };
+    let level = match env.or(self.opt_level).or(profile) {
+      Some(ol) => ol,
+      None     =>
+        match &*self.getenv_unwrap("OPT_LEVEL")? {
+          "s" | "z" => self.opt_level_for_size,
+          l => l.parse().map_err(|_| Error::InvalidConfig(format!("unknown OPT_LEVEL `{}`", l)))?,
+        },
+    };
+    Ok(cmp::min(level, 3))
+  }
+
+  fn get_addressing(&self) -> Result<Addr, Error> {
+    Ok(match self.addressing {
+      Addr::Auto =>
+        match self.getenv("CARGO_CFG_TARGET_POINTER_WIDTH") {
+          Some(ref w) if w == "64" => Addr::A64,
+          Some(_)                  => Addr::A32,
+          None if self.get_arch()? == Arch::X86_64 ||
+                  self.get_arch()? == Arch::Aarch64 => Addr::A64,
+          None                     => Addr::A32,
+        },
+      a => a,
+    })
+  }
+
+  fn get_debug(&self) -> Result<bool, Error> {
+    match self.debug {
+      Some(d) => Ok(d),
+      None    => Ok(self.getenv_unwrap("PROFILE")? == "debug"),
+    }
+  }
+
+  fn get_cpus(&self) -> Result<Option<Vec<Cpu>>, Error> {
+    if !self.target_cpus.is_empty() {
+      let mut cpus = vec![];
+      for t in self.get_targets()? {
+        match self.target_cpus.iter().find(|&&(ref pt, _)| *pt == t) {
+          Some(&(_, ref c)) => cpus.push(c.clone()),
+          None => return Err(Error::InvalidConfig(format!(
+                    "target `{}` has no CPU paired with it, but other targets do", t.to_str()))),
+        }
+      }
+      return Ok(Some(cpus));
+    }
+
+    match self.cpu.clone() {
+      None if self.native => Ok(Some(vec![ self.get_native()?.0 ])),
+      c => Ok(c),
+    }
+  }
+
+  fn get_native(&self) -> Result<(Cpu, Target), Error> {
+    if self.getenv_unwrap("HOST")? != self.getenv_unwrap("TARGET")? {
+      return Err(Error::InvalidConfig("cpu_native() cannot be used when cross compiling".into()));
+    }
+    detect_host().ok_or_else(|| {
+      Error::InvalidConfig("cpu_native() is only supported on x86 build hosts".into())
+    })
+  }
+
+  fn get_targets(&self) -> Result<Vec<Target>, Error> {
+    self.supported_targets.try_get(|| {
+      let targets = self.get_host_targets()?;
+
+      let supported = match self.get_support_matrix() {
+        Some(s) => s,
+        None    => return Ok(targets),
+      };
+
+      let (ok, missing): (Vec<Target>, Vec<Target>) =
+        targets.into_iter().partition(|t| supported.iter().any(|s| is_spelling_of(s, t.to_str())));
+
+      if missing.is_empty() { return Ok(ok); }
+
+      let names: Vec<String> = missing.iter().map(|t| t.to_str().to_string()).collect();
+
+      if !self.filter_unsupported || ok.is_empty() {
+        return Err(Error::UnsupportedTarget { targets: names });
+      }
+
+      println!("cargo:warning=skipping target(s) unsupported by ispc: {}", names.join(", "));
+      Ok(ok)
+    })
+  }
+
+  // The targets ispc reports in its `--support-matrix`, or `None` if this
+  // version of ispc can't tell us.
+  fn get_support_matrix(&self) -> Option<Vec<String>> {
+    let mut t = self.get_base_compiler().ok()?;
+    t.arg("--support-matrix");
+
+    let output = match probe(&t) {
+      Ok(p) if p.status.success() => p.stdout,
+      _ => return None,
+    };
+
+    // Each row starts with a target name, while the header is indented.
+    let targets: Vec<String> =
+      output.lines()
+        .filter(|l| !l.starts_with(char::is_whitespace))
+        .filter_map(|l| l.split_whitespace().next())
+        .filter(|t| !t.chars().all(|c| c == '-'))
+        .map(|t| t.to_string())
+        .collect();
+
+    if targets.is_empty() { None } else { Some(targets) }
+  }
+
+  fn get_host_targets(&self) -> Result<Vec<Target>, Error> {
+    let targets = self.get_all_targets()?;
+
+    if !self.get_host_only()? { return Ok(targets); }
+
+    let host = match detect_host() {
+      Some((_, t)) => t.isa_level().unwrap(),
+      None         => return Ok(targets),
+    };
+
+    let best =
+      targets.iter()
+        .filter(|t| t.isa_level().is_some_and(|l| l <= host))
+        .max_by_key(|t| t.isa_level());
+
+    match best {
+      Some(t) => Ok(vec![ t.clone() ]),
+      None    => Ok(targets),
+    }
+  }
+
+  fn get_host_only(&self) -> Result<bool, Error> {
+    let enabled =
+      self.host_only.unwrap_or_else(|| {
+        self.getenv("RISPC_HOST_ONLY").is_some_and(|v| v == "1" || v == "true")
+      });
+    // The profile itself, since `debug` only asks for debug info.
+    let debug_profile = self.getenv_unwrap("PROFILE")? == "debug";
+    Ok(enabled && debug_profile && self.getenv_unwrap("HOST")? == self.getenv_unwrap("TARGET")?)
+  }
+
+  fn get_all_targets(&self) -> Result<Vec<Target>, Error> {
+    Ok(match self.targets.clone() {
+      None if self.native => vec![ self.get_native()?.1 ],
+      None if self.get_arch()? == Arch::Xe64  => vec![ Target::Gen9_x8 ],
+      None if self.features => {
+        let baseline = self.get_feature_level();
+        let defaults = self.get_default_targets()?;
+        let kept: Vec<Target> = defaults.iter()
+          .filter(|t| !matches!(t.isa_level(), Some(l) if l < baseline))
+          .cloned()
+          .collect();
+        // Such as for AVX-512, above everything in the defaults. The best of
+        // them still runs.
+        if kept.is_empty() {
+          defaults.into_iter().max_by_key(|t| t.isa_level()).into_iter().collect()
+        } else {
+          kept
+        }
+      },
+      None    => self.get_default_targets()?,
+      Some(ts) => {
+        let mut uniq = Vec::with_capacity(ts.len());
+        for t in ts {
+          if !uniq.contains(&t) { uniq.push(t); }
+        }
+        uniq
+      },
+    })
+  }
+
+  fn check_targets(&self) -> Result<(), Error> {
+    let targets = self.get_targets()?;
+
+    if targets.is_empty() {
+      return Err(Error::InvalidConfig("no targets are selected".into()));
+    }
+
+    for (i, a) in targets.iter().enumerate() {
+      for b in targets[..i].iter() {
+        if self.get_obj_suffix(a) == self.get_obj_suffix(b) {
+          return Err(Error::InvalidConfig(format!(
+            "targets `{}` and `{}` are for the same ISA, but only one width per ISA \
+             may be selected", b.to_str(), a.to_str())));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn get_default_targets(&self) -> Result<Vec<Target>, Error> {
+    let triple = self.getenv_unwrap("TARGET")?;
+    Ok(self.get_triple_rule(&triple, |r| if r.targets.is_empty() { None } else { Some(r.targets.clone()) })
+      .unwrap_or_else(|| vec![ Target::Sse2, Target::Sse4, Target::Avx1, Target::Avx1_1, Target::Avx2 ]))
+  }
+
+  fn get_triple_rule<T, F: Fn(&TripleRule) -> Option<T>>(&self, triple: &str, f: F) -> Option<T> {
+    self.triple_rules.iter()
+      .chain(builtin_triple_rules().iter())
+      .filter(|r| r.matches(triple))
+      .find_map(f)
+  }
+
+  fn get_feature_level(&self) -> u32 {
+    let features = self.getenv("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    let has = |f: &str| features.split(',').any(|x| x == f);
+    if      has("avx512f") && has("avx512bw") &&
+            has("avx512dq") && has("avx512vl")         { 5 }
+    else if has("avx2")                                { 4 }
+    else if has("avx") && has("f16c") && has("rdrand") { 3 }
+    else if has("avx")                                 { 2 }
+    else if has("sse4.1")                              { 1 }
+    else                                               { 0 }
+  }
+
+  fn get_target_arch(&self) -> Result<Arch, Error> {
+    let t = self.getenv_unwrap("TARGET")?;
+    self.get_triple_rule(&t, |r| r.arch).ok_or(Error::UnsupportedArch { triple: t })
+  }
+
+  fn get_arch(&self) -> Result<Arch, Error> {
+    if self.emit_spirv { Ok(Arch::Xe64) }
+    else if let Some(x) = self.architecture { Ok(x) }
+    else { self.get_target_arch() }
+  }
+
+  fn get_obj_suffix(&self, t: &Target) -> String {
+    match *t {
+      Target::Custom(ref name) =>
+        self.custom_suffixes.iter()
+          .find(|&&(ref n, _)| n == name)
+          .map(|&(_, ref suffix)| suffix.clone())
+          .unwrap_or_else(|| t.obj_suffix()),
+      _ => t.obj_suffix(),
+    }
+  }
+
+  // The OS to pass as `--target-os`, or `None` to let ispc default to the OS of
+  // the build machine.
+  fn get_target_os(&self) -> Result<Option<TargetOs>, Error> {
+    if self.target_os.is_some() { return Ok(self.target_os); }
+    let target = self.get_triple_rule(&self.getenv_unwrap("TARGET")?, |r| r.target_os);
+    if target == self.get_triple_rule(&self.getenv_unwrap("HOST")?, |r| r.target_os) { Ok(None) }
+    else { Ok(target) }
+  }
+
+  fn get_sysroot(&self) -> Result<Option<PathBuf>, Error> {
+    if self.sysroot.is_some() { return Ok(self.sysroot.clone()); }
+    let os = self.get_triple_rule(&self.getenv_unwrap("TARGET")?, |r| r.target_os);
+    if os != Some(TargetOs::Ios) { return Ok(None); }
+    Ok(self.getenv("SDKROOT").map(PathBuf::from))
+  }
+
+  fn get_generic(&self) -> Result<bool, Error> {
+    let targets = self.get_targets()?;
+    if !targets.iter().any(|t| t.is_generic()) { Ok(false) }
+    else if targets.len() == 1 { Ok(true) }
+    else { Err(Error::InvalidConfig("a generic target must be the only target selected".into())) }
+  }
+
+  fn get_dll_export(&self) -> Result<bool, Error> {
+    match self.dll_export {
+      Some(d) => Ok(d),
+      None    => Ok(self.getenv_unwrap("TARGET")?.contains("windows-msvc")),
+    }
+  }
+
+  fn get_color(&self) -> bool {
+    match self.color {
+      ColorChoice::Always => return true,
+      ColorChoice::Never  => return false,
+      ColorChoice::Auto   => {},
+    }
+    match self.getenv("CARGO_TERM_COLOR") {
+      Some(ref c) if c == "always" => return true,
+      Some(ref c) if c == "never"  => return false,
+      _ => {},
+    }
+    self.getenv("NO_COLOR").unwrap_or_default().is_empty()
+      && self.getenv("TERM").is_some_and(|t| t != "dumb")
+      && io::stderr().is_terminal()
+  }
+
+  fn get_vectorcall(&self) -> Result<bool, Error> {
+    Ok(self.vectorcall && self.get_target_windows()?)
+  }
+
+  fn get_target_windows(&self) -> Result<bool, Error> {
+    Ok(self.get_triple_rule(&self.getenv_unwrap("TARGET")?, |r| r.target_os) == Some(TargetOs::Windows))
+  }
+
+  fn get_pic(&self) -> Result<Pic, Error> {
+    let arch = self.get_arch()?;
+    if arch == Arch::Wasm32 || arch == Arch::Xe64 { return Ok(Pic::None); }
+    if let Some(x) = self.pic { return Ok(x); }
+    // rustc reports `pic` there too, but Windows objects have no such thing.
+    if self.get_target_windows()? { return Ok(Pic::None); }
+    let target = self.getenv_unwrap("TARGET")?;
+    if let Some(x) = self.get_relocation_model().or_else(|| self.get_triple_rule(&target, |r| r.pic)) {
+      return Ok(x);
+    }
+    // Static musl binaries are static-pie unless told otherwise.
+    let musl = self.getenv("CARGO_CFG_TARGET_ENV").map_or(target.contains("musl"), |e| e == "musl");
+    let crt_static = self.getenv("CARGO_CFG_TARGET_FEATURE")
+      .is_some_and(|f| f.split(',').any(|f| f == "crt-static"));
+    Ok(if musl && crt_static { Pic::Pie } else { Pic::Pic })
+  }
+
+  // The relocation model rustc builds with, from the cfg nightly reports, or
+  // else from `-C relocation-model` in the flags cargo passes it.
+  fn get_relocation_model(&self) -> Option<Pic> {
+    let model = self.getenv("CARGO_CFG_RELOCATION_MODEL").or_else(|| {
+      let flags = self.getenv("CARGO_ENCODED_RUSTFLAGS")?;
+      let flags: Vec<&str> = flags.split('\x1f').collect();
+      let mut model = None;
+      for (i, f) in flags.iter().enumerate() {
+        let opt = match *f {
+          "-C" | "--codegen" => flags.get(i + 1).cloned(),
+          f                  => f.strip_prefix("-C").or_else(|| f.strip_prefix("--codegen=")),
+        };
+        // The last one wins, as with rustc.
+        if let Some(m) = opt.and_then(|o| o.strip_prefix("relocation-model=")) {
+          model = Some(m.to_string());
+        }
+      }
+      model
+    })?;
+    match &*model {
+      "pic" | "default" => Some(Pic::Pic),
+      "pie"             => Some(Pic::Pie),
+      "static" | "dynamic-no-pic" | "ropi" | "rwpi" | "ropi-rwpi" => Some(Pic::None),
+      _                 => None,
+    }
+  }
+
+  // wasm objects need an archiver which understands them well enough to write
+  // a symbol index, which GNU `ar` does not. Prefer `llvm-ar` unless the user
+  // has already picked one.
+  fn get_archiver(&self) -> Result<Option<PathBuf>, Error> {
+    if let Some(ref ar) = self.archiver { return Ok(Some(ar.clone())); }
+    if self.get_arch()? != Arch::Wasm32 { return Ok(None); }
+    if self.get_archiver_env()?.is_some() { Ok(None) }
+    else { Ok(Some(PathBuf::from("llvm-ar"))) }
+  }
+
+  fn get_archiver_env(&self) -> Result<Option<PathBuf>, Error> {
+    let target = self.getenv_unwrap("TARGET")?;
+    let vars = [ format!("AR_{}", target),
+                 format!("AR_{}", target.replace("-", "_")),
+                 "TARGET_AR".to_string(),
+                 "AR".to_string() ];
+    Ok(vars.iter().filter_map(|v| self.getenv(v)).next().map(PathBuf::from))
+  }
+
+  // With a single target, its own definitions can go in with the rest. See
+  // `compile_per_target` for the multi-target case.
+  fn get_defs(&self) -> Result<Vec<(String, Option<String>)>, Error> {
+    let mut defs = self.definitions.clone();
+    let targets = self.get_targets()?;
+    if targets.len() == 1 { defs.extend(self.get_target_defs(&targets[0])); }
+    Ok(defs)
+  }
+
+  fn get_target_defs(&self, t: &Target) -> Vec<(String, Option<String>)> {
+    self.target_definitions.iter()
+      .filter(|&&(ref dt, _, _)| dt == t)
+      .map(|&(_, ref k, ref v)| (k.clone(), v.clone()))
+      .collect()
+  }
+
+  fn needs_per_target(&self) -> Result<bool, Error> {
+    let targets = self.get_targets()?;
+    Ok(targets.len() > 1 &&
+       self.target_definitions.iter().any(|&(ref t, _, _)| targets.contains(t)))
+  }
+
+  fn get_out_dir(&self) -> Result<PathBuf, Error> {
+    if let Some(ref d) = self.out_dir { return Ok(std::env::current_dir()?.join(d)); }
+    std::env::var_os("OUT_DIR").map(PathBuf::from).ok_or(Error::MissingEnv("OUT_DIR".into()))
+  }
+
+  fn get_archive_name(&self, libname: &str) -> Result<String, Error> {
+    if self.getenv_unwrap("TARGET")?.contains("msvc") {
+      Ok(format!("{}.lib", libname))
+    } else {
+      Ok(format!("lib{}.a", libname))
+    }
+  }
+
+  fn get_static_crt(&self) -> Result<bool, Error> {
+    let rust = self.getenv("CARGO_CFG_TARGET_FEATURE")
+      .is_some_and(|f| f.split(',').any(|f| f == "crt-static"));
+    match self.static_crt {
+      Some(crt) if crt != rust && self.is_msvc()? => {
+        println!("cargo:warning=static_crt({}) mixes C runtimes with Rust code, which {} \
+                  crt-static", crt, if rust { "enables" } else { "doesn't enable" });
+        Ok(crt)
+      },
+      Some(crt) => Ok(crt),
+      None      => Ok(rust),
+    }
+  }
+
+  fn is_msvc(&self) -> Result<bool, Error> {
+    Ok(self.getenv_unwrap("TARGET")?.contains("msvc"))
+  }
+
+  fn get_shared_name(&self, libname: &str) -> Result<String, Error> {
+    let target = self.getenv_unwrap("TARGET")?;
+    if self.get_target_windows()? {
+      Ok(format!("{}.dll", libname))
+    } else if target.contains("apple") {
+      Ok(format!("lib{}.dylib", libname))
+    } else {
+      Ok(format!("lib{}.so", libname))
+    }
+  }
+
+  // The suffix of each combination of the values of `specializations`, and the
+  // definitions it's compiled with. Without any, there's just the one. With
+  // `rust_dispatch`, each is compiled for each target on its own, too.
+  fn get_specializations(&self) -> Result<Vec<Specialization>, Error> {
+    let mut specs = vec![(String::new(), vec![])];
+    for &(ref name, ref values) in &self.specializations {
+      if values.is_empty() {
+        return Err(Error::InvalidConfig(format!("`{}` is specialized for no values", name)));
+      }
+      let word = name.split('_').find(|w| !w.is_empty()).unwrap_or(name).to_lowercase();
+      let mut next = vec![];
+      for &(ref suffix, ref defs) in &specs {
+        for v in values {
+          if v.is_empty() || !v.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(Error::InvalidConfig(format!(
+              "the value `{}` of `{}` can't be part of a function name", v, name)));
+          }
+          let mut defs = defs.clone();
+          defs.push((name.clone(), v.clone()));
+          next.push((format!("{}_{}{}", suffix, word, v), defs));
+        }
+      }
+      specs = next;
+    }
+    if !self.rust_dispatch {
+      return Ok(specs.into_iter().map(|(suffix, defs)| (suffix, defs, None)).collect());
+    }
+    let targets = self.get_targets()?;
+    for t in &targets {
+      if t.features().is_none() {
+        return Err(Error::InvalidConfig(format!("rust_dispatch can't detect support for the \
+                                                 target `{}`", t.to_str())));
+      }
+    }
+    Ok(specs.into_iter().flat_map(|(suffix, defs)| {
+      targets.iter().map(move |t| {
+        (format!("{}_{}", suffix, self.get_obj_suffix(t)), defs.clone(), Some(t.clone()))
+      })
+    }).collect())
+  }
+
+  fn get_intermediate_dir(&self, libname: &str) -> Result<PathBuf, Error> {
+    let out_dir = self.get_out_dir()?;
+    Ok(match self.intermediate_dir {
+      Some(ref d) => out_dir.join(d),
+      None        => out_dir.join("rispc").join(libname),
+    })
+  }
+
+  fn getenv(&self, v: &str) -> Option<String> {
+    watch_env(v);
+    let r = std::env::var(v).ok();
+    if self.get_verbosity() == Verbosity::Verbose {
+      println!("{} = {:?}", v, r);
+    }
+    r
+  }
+
+  fn get_jobs(&self) -> usize {
+    let jobs = self.jobs.or_else(|| {
+      ["NUM_JOBS", "CARGO_BUILD_JOBS"].iter()
+        .filter_map(|v| self.getenv(v))
+        .filter_map(|n| n.parse().ok())
+        .next()
+    });
+    cmp::max(jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get())), 1)
+  }
+
+  // Read without `getenv`, which depends on the result.
+  fn get_verbosity(&self) -> Verbosity {
+    watch_env("RISPC_VERBOSE");
+    match std::env::var("RISPC_VERBOSE") {
+      Ok(ref v) if v == "0" || v == "silent"  => Verbosity::Silent,
+      Ok(ref v) if v == "1" || v == "normal"  => Verbosity::Normal,
+      Ok(ref v) if v == "2" || v == "verbose" => Verbosity::Verbose,
+      Ok(v) => {
+        warn_once(&format!("ignoring RISPC_VERBOSE=`{}`, which must be one of 0, 1, 2, \
+                            silent, normal or verbose", v));
+        self.verbosity.unwrap_or(Verbosity::Normal)
+      },
+      Err(_) => self.verbosity.unwrap_or(Verbosity::Normal),
+    }
+  }
+
+  fn getenv_unwrap(&self, v: &str) -> Result<String, Error> {
+    self.getenv(v).ok_or_else(|| Error::MissingEnv(v.into()))
+  }
+
+  fn get_help(&self) -> String {
+    self.help.get(|| {
+      let mut t = match self.get_base_compiler() {
+        Ok(t)  => t,
+        Err(_) => return String::new(),
+      };
+      t.arg("--help");
+      match probe(&t) {
+        Ok(p)  => p.stdout + &p.stderr,
+        Err(_) => String::new(),
+      }
+    })
+  }
+
+  // The spelling of `flag` the installed ispc understands, or `None` after
+  // warning that `option`, which needs it, has no effect. Everything is
+  // assumed to be understood by an ispc whose `--help` can't be read.
+  fn version_flag(&self, flag: &str, option: &str) -> Option<String> {
+    if self.get_help().is_empty() || self.is_flag_supported(flag) { return Some(flag.into()); }
+    match old_spelling(flag) {
+      Some(old) if self.is_flag_supported(old) => Some(old.into()),
+      _ => {
+        warn_once(&format!("the installed ispc has no `{}`, so `{}` has no effect", flag, option));
+        None
+      },
+    }
+  }
+
+  fn version_arg(&self, t: &mut Tool, flag: &str, option: &str) {
+    if let Some(f) = self.version_flag(flag, option) { t.arg(&f); }
+  }
+
+  // Like `version_flag`, for things such as target names which can't simply
+  // be left out when the installed ispc doesn't know them.
+  fn get_spelling(&self, name: &str) -> String {
+    match old_spelling(name) {
+      Some(old) if !self.is_flag_supported(name) && self.is_flag_supported(old) => old.into(),
+      _ => name.into(),
+    }
+  }
+
+  fn is_flag_supported(&self, flag: &str) -> bool {
+    let help = self.get_help();
+    let mut parts = flag.splitn(2, '=');
+    let name = parts.next().unwrap();
+    help.contains(name) && parts.all(|v| help.contains(v))
+  }
+
+  fn get_base_compiler(&self) -> Result<Tool, Error> {
+    let mut t = Tool::new(PathBuf::from(self.getenv("ISPC").unwrap_or("ispc".into())));
+    t.envs = self.envs.clone();
+    t.cwd = self.get_current_dir()?;
+    Ok(t)
+  }
+
+  fn get_current_dir(&self) -> Result<Option<PathBuf>, Error> {
+    match self.current_dir {
+      Some(ref d) => Ok(Some(std::env::current_dir()?.join(d))),
+      None        => Ok(None),
+    }
+  }
+
+  // Paths given to the build are relative to the build script's working
+  // directory. When ispc runs elsewhere, refer to the same files relative to
+  // ispc's directory where possible, so the names it records are stable.
+  fn get_source_path(&self, p: &Path) -> Result<PathBuf, Error> {
+    let dir = match self.get_current_dir()? {
+      Some(dir) => dir,
+      None      => return self.get_tool_path(p),
+    };
+    let abs = std::env::current_dir()?.join(p);
+    match abs.strip_prefix(&dir) {
+      Ok(rel) => self.get_tool_path(rel),
+      Err(_)  => self.get_tool_path(&abs),
+    }
+  }
+
+  // `p`, which is relative to the directory ispc runs in, as it should be
+  // passed to ispc.
+  fn get_tool_path(&self, p: &Path) -> Result<PathBuf, Error> {
+    Ok(match self.get_current_dir()? {
+      Some(dir) => native_path(p, &dir),
+      None      => native_path(p, &std::env::current_dir()?),
+    })
+  }
+
+  fn basic_tool(&self) -> Result<Tool, Error> {
+    let mut t = self.get_base_compiler()?;
+
+    match self.get_addressing()? {
+      Addr::A64 => { t.arg("--addressing=64"); },
+      _         => { t.arg("--addressing=32"); },
+    }
+
+    match self.get_arch()? {
+      Arch::X86 => t.arg("--arch=x86"),
+      Arch::X86_64 => t.arg("--arch=x86_64"),
+      Arch::Wasm32 => t.arg("--arch=wasm32"),
+      Arch::Arm => t.arg("--arch=arm"),
+      Arch::Aarch64 => t.arg("--arch=aarch64"),
+      Arch::Xe64 => t.arg(self.get_spelling("--arch=xe64")),
+    };
+
+    if let Some(os) = self.get_target_os()? {
+      self.version_arg(&mut t, &format!("--target-os={}", os.to_str()), "target_os");
+    }
+
+    for i in &self.includes {
+      t.arg("-I").arg(self.get_source_path(i)?);
+    }
+
+    if let Some(sysroot) = self.get_sysroot()? {
+      t.arg("-I").arg(self.get_tool_path(&sysroot.join("usr").join("include"))?);
+    }
+
+    if self.get_color() { self.version_arg(&mut t, "--colored-output", "color"); }
+
+    match self.get_cpus()? {
+      None => {},
+      Some(cpus) => {
+        let mut cpu_s = String::new();
+
+        for (i, c) in cpus.into_iter().enumerate() {
+          cpu_s.push_str(if i == 0 { "--cpu=" } else { "," });
+          cpu_s.push_str(c.to_str());
+        }
+
+        t.arg(&*cpu_s);
+      }
+    }
+
+    for (k, ov) in self.get_defs()? {
+      match ov {
+        None    => { t.arg(&*format!("-D{}", k)); },
+        Some(v) => { t.arg(&*format!("-D{}={}", k, v)); },
+      }
+    }
+
+    if let Some(m) = self.code_model {
+      self.version_arg(&mut t, &format!("--mcmodel={}", m.to_str()), "code_model");
+    }
+
+    if let Some(align) = self.force_alignment {
+      t.arg(&*format!("--force-alignment={}", align));
+    }
+
+    if self.get_debug()? {
+      t.arg("-g");
+      if let Some(version) = self.dwarf_version {
+        self.version_arg(&mut t, &format!("--dwarf-version={}", version), "dwarf_version");
+      }
+    }
+
+    t.arg(&*format!("--math-lib={}", self.math_lib.to_str()));
+
+    if self.no_stdlib { self.version_arg(&mut t, "--nostdlib", "no_stdlib"); }
+    if self.no_cpp { self.version_arg(&mut t, "--nocpp", "no_cpp"); }
+    if self.no_omit_frame_pointer {
+      self.version_arg(&mut t, "--no-omit-frame-pointer", "no_omit_frame_pointer");
+    }
+    if self.instrument { self.version_arg(&mut t, "--instrument", "instrument"); }
+    if self.get_dll_export()? { self.version_arg(&mut t, "--dllexport", "dll_export"); }
+    if self.get_vectorcall()? { self.version_arg(&mut t, "--vectorcall", "vectorcall"); }
+
+    t.arg(&*format!("-O{:?}", self.get_opt_level()?));
+
+    // Only these are listed by `--help`; the rest are in `--help-dev`.
+    for &f in &self.opts {
+      let flag = format!("--opt={}", f.to_str());
+      match f {
+        OptFlag::Disable_zmm | OptFlag::Reset_ftz_daz => self.version_arg(&mut t, &flag, "opt"),
+        _ => { t.arg(&flag); },
+      }
+    }
+
+    if self.get_pic()? != Pic::None && !self.get_generic()? { t.arg("--pic"); }
+
+    let mut target_s = String::new();
+
+    for (i, t) in self.get_targets()?.into_iter().enumerate() {
+      target_s.push_str(if i == 0 { "--target=" } else { "," });
+      target_s.push_str(&self.get_spelling(t.to_str()));
+    }
+
+    if !target_s.is_empty() { t.arg(&*target_s); }
+
+    if self.werror && self.warning_filters.is_empty() { self.version_arg(&mut t, "--werror", "werror"); }
+    if !self.warnings { self.version_arg(&mut t, "--woff", "warn"); }
+    if !self.wperf { self.version_arg(&mut t, "--wno-perf", "warn_perf"); }
+
+    for f in &self.flags { t.arg(f); }
+
+    for f in &self.flags_if_supported {
+      if self.is_flag_supported(f) { t.arg(f); }
+    }
+
+    for f in self.get_env_flags()? { t.arg(&f); }
+
+    Ok(t)
+  }
+
+  fn get_env_flags(&self) -> Result<Vec<String>, Error> {
+    let target = self.getenv_unwrap("TARGET")?;
+    let scoped = self.getenv(&format!("RISPC_FLAGS_{}", target))
+      .or_else(|| self.getenv(&format!("RISPC_FLAGS_{}", target.replace("-", "_"))));
+    let mut flags = vec![];
+    for s in self.getenv("RISPC_FLAGS").iter().chain(scoped.iter()) {
+      flags.extend(s.split_whitespace().map(String::from));
+    }
+    Ok(flags)
+  }
+
+  fn get_emit_args(&self) -> Result<Vec<OsString>, Error> {
+    Ok(if self.emit_spirv {
+      vec![ "--emit-spirv".into() ]
+    } else if self.get_generic()? {
+      let mut args = vec![ "--emit-c++".into() ];
+      if let Some(ref inc) = self.cxx_include_file {
+        // Kept absolute, since the generated C++ is compiled from elsewhere.
+        let inc = match self.get_current_dir()? {
+          Some(_) => std::env::current_dir()?.join(inc),
+          None    => inc.clone(),
+        };
+        let mut arg = OsString::from("--c++-include-file=");
+        arg.push(self.get_tool_path(&inc)?);
+        args.push(arg);
+      }
+      args
+    } else {
+      vec![ "--emit-obj".into() ]
+    })
+  }
+
+  fn for_file<'a>(&'a self, file: &Path) -> Cow<'a, Config> {
+    let opts = match self.file_opts.iter().find(|&&(ref p, _)| p == file) {
+      Some(&(_, ref o)) => o,
+      None => return Cow::Borrowed(self),
+    };
+
+    let mut c = self.clone();
+    for d in &opts.definitions {
+      c.definitions.retain(|&(ref k, _)| *k != d.0);
+      c.definitions.push(d.clone());
+    }
+    for &(f, v) in &opts.opts { c.opt(f, v); }
+    if let Some(m) = opts.math_lib { c.math_lib = m; }
+    if let Some(ref t) = opts.targets { c.targets = Some(t.clone()); }
+    Cow::Owned(c)
+  }
+
+  fn compile_llvm(&self, file: &Path, dep: &Path, obj: &Path, mut t: Tool) -> Result<(), Error> {
+    let (flag, ext) = match self.emit_llvm {
+      LlvmOutput::None    => return Ok(()),
+      LlvmOutput::Bitcode => ("--emit-llvm", "bc"),
+      LlvmOutput::Text    => ("--emit-llvm-text", "ll"),
+    };
+    let flag = match self.version_flag(flag, "emit_llvm") {
+      Some(f) => f,
+      None    => return Ok(()),
+    };
+
+    t.arg(&flag)
+     .arg(self.get_source_path(file)?)
+     .arg("-o")
+     .arg(self.get_tool_path(&obj.with_extension(ext))?);
+
+    self.run_cached(&t, file, dep, &[ &obj.with_extension(ext) ])
+  }
+
+  fn compile_asm(&self, file: &Path, dep: &Path, obj: &Path, mut t: Tool) -> Result<(), Error> {
+    if !self.emit_assembly { return Ok(()); }
+
+    match (self.asm_syntax, self.get_arch()?) {
+      (Some(s), Arch::X86) | (Some(s), Arch::X86_64) => {
+        self.version_arg(&mut t, &format!("--x86-asm-syntax={}", s.to_str()), "asm_syntax");
+      },
+      _ => {},
+    }
+
+    t.arg("--emit-asm")
+     .arg(self.get_source_path(file)?)
+     .arg("-o")
+     .arg(self.get_tool_path(&obj.with_extension("s"))?);
+
+    self.run_cached(&t, file, dep, &[ &obj.with_extension("s") ])
+  }
+
+  // Compiles `file` once per target, each time with that target's definitions,
+  // into a scratch directory next to `obj`. The first run is the whole
+  // multi-target build, for the dispatcher and header, which don't depend on
+  // the definitions; only its first target's object is kept. The others build
+  // their target alone, then their exports get the names the dispatcher calls,
+  // and the rest of their symbols are hidden from each other. That needs
+  // objcopy, so for MSVC they repeat the whole build, keeping one object each.
+  // Returns each run's dependency file, since the definitions may select other
+  // `#include`s.
+  fn compile_per_target(&self, file: &Path, hdr: Option<&Path>, dep: &Path, obj: &Path,
+                        base: &Tool, llvm_base: &Tool) -> Result<Vec<PathBuf>, Error> {
+    let lfile = obj.file_stem().unwrap();
+    let objdir = obj.parent().unwrap();
+    let exts = ["o", "bc", "ll", "s"];
+    let targets: Vec<(usize, Target)> = self.get_targets()?.into_iter().enumerate().collect();
+
+    parallel_map(self.get_jobs(), &targets, |&(i, ref t)| {
+      let suffix = self.get_obj_suffix(t);
+      let tdir = objdir.join(file_name(lfile, "targets")).join(&suffix);
+      let tobj = tdir.join(obj.file_name().unwrap());
+
+      let mut tool = base.clone();
+      let mut llvm_tool = llvm_base.clone();
+      for (k, ov) in self.get_target_defs(t) {
+        let d = match ov {
+          None    => format!("-D{}", k),
+          Some(v) => format!("-D{}={}", k, v),
+        };
+        tool.arg(&d);
+        llvm_tool.arg(&d);
+      }
+
+      let alone = i > 0 && !self.is_msvc()?;
+      let mut cfg = self.clone();
+      if alone {
+        cfg.targets = Some(vec![ t.clone() ]);
+        cfg.supported_targets = Cache::new();
+        let flag = OsString::from(format!("--target={}", self.get_spelling(t.to_str())));
+        for a in tool.args.iter_mut().chain(llvm_tool.args.iter_mut()) {
+          if a.to_string_lossy().starts_with("--target=") { *a = flag.clone(); }
+        }
+      }
+
+      // The header of a run alone lists the exports to rename.
+      let thdr = tdir.join(file_name(lfile, "h"));
+      let thdr = if i == 0 { hdr } else if alone { Some(&*thdr) } else { None };
+      let tdep = if i == 0 { dep.to_path_buf() } else { tdir.join(dep.file_name().unwrap()) };
+      cfg.compile_object(file, thdr, &tdep, &tobj, tool)?;
+      cfg.compile_llvm(file, &tdep, &tobj, llvm_tool.clone())?;
+      cfg.compile_asm(file, &tdep, &tobj, llvm_tool)?;
+
+      if alone {
+        let thdr = [ thdr.unwrap().to_path_buf() ];
+        let tobj = [ tobj.clone() ];
+        cfg.rename_symbols("", &format!("_{}", suffix), &tdir, &thdr, &tobj)?;
+        cfg.hide_symbols(&thdr[0], &tobj)?;
+      }
+
+      let mut keep = vec![];
+      if alone {
+        keep.push((lfile.to_owned(), target_stem(lfile, &suffix)));
+      } else {
+        keep.push((target_stem(lfile, &suffix), target_stem(lfile, &suffix)));
+        if i == 0 { keep.push((lfile.to_owned(), lfile.to_owned())); }
+      }
+      for &(ref from, ref to) in &keep {
+        for ext in &exts {
+          let from = tdir.join(file_name(from, ext));
+          if from.exists() {
+            fs::copy(&from, objdir.join(file_name(to, ext)))?;
+          }
+        }
+      }
+      Ok(tdep)
+    }).into_iter().collect()
+  }
+
+  // Returns the diagnostics left after `warning_filters`.
+  fn run_ispc(&self, t: &Tool) -> Result<String, Error> {
+    let verbosity = self.get_verbosity();
+    let mut attempt = 0;
+    let (status, stdout, diags) = loop {
+      let r = capture(t, verbosity, self.timeout, &mut self.warning_filter());
+      // Only failing to run ispc, or to read its output, is worth retrying; an
+      // error in the source or a hang would only happen again.
+      let flaky = matches!(r, Err(Error::MissingCompiler { .. }) | Err(Error::Io(_)));
+      if !flaky || attempt >= self.retries { break r?; }
+      attempt += 1;
+      if verbosity >= Verbosity::Normal {
+        println!("couldn't run ispc, retrying ({} of {})", attempt, self.retries);
+      }
+    };
+    let cwd = match t.cwd {
+      Some(ref d) => d.clone(),
+      None        => std::env::current_dir()?,
+    };
+
+    let failed = match status { Some(s) => !s.success(), None => true };
+    if failed {
+      // The raw output was already printed as it arrived, unless silent.
+      if verbosity < Verbosity::Normal && !stdout.is_empty() {
+        print!("\n--- stdout ---\n{}\n--- end stdout ---\n\n", stdout);
+      }
+      print_snippets(&diags, t.targets(), &cwd);
+      return Err(match status {
+        Some(s) => Error::CompilerExited { status: s, stderr: diags },
+        None    => Error::TimedOut { timeout: self.timeout.unwrap(), stderr: diags },
+      });
+    }
+
+    // With warnings filtered, `--werror` can't be passed to ispc, or it would
+    // fail on the filtered ones. Fail here on what's left instead.
+    if self.werror && !self.warning_filters.is_empty() &&
+       diags.lines().any(|l| strip_ansi(l).contains(": Warning: ")) {
+      print_snippets(&diags, t.targets(), &cwd);
+      return Err(Error::Warnings { perf: false, stderr: diags });
+    }
+
+    if self.werror_perf && diags.lines().any(|l| strip_ansi(l).contains(": Performance Warning: ")) {
+      print_snippets(&diags, t.targets(), &cwd);
+      return Err(Error::Warnings { perf: true, stderr: diags });
+    }
+
+    print_warnings(&diags);
+    Ok(diags)
+  }
+
+  // Fed ispc's diagnostics line by line, splits them at each
+  // `file:line:col: Kind: message` line, and drops every disabled warning along
+  // with the source snippet following it.
+  fn warning_filter(&self) -> impl FnMut(&str) -> bool + Send + '_ {
+    let mut keep = true;
+    move |line| {
+      if self.warning_filters.is_empty() { return true; }
+      let plain = strip_ansi(line);
+      if plain.contains(": Warning: ") || plain.contains(": Performance Warning: ") {
+        keep = !self.warning_filters.iter().any(|w| w.matches(&plain));
+      } else if plain.contains(": Error: ") {
+        keep = true;
+      }
+      keep
+    }
+  }
+
+  fn compile_object(&self, file: &Path, hdr: Option<&Path>, dep: &Path, dst: &Path,
+                    mut t: Tool) -> Result<(), Error> {
+    fs::create_dir_all(dst.parent().unwrap())?;
+
+    if let Some(hdr) = hdr {
+      t.arg("-h").arg(self.get_tool_path(hdr)?);
+    }
+
+    t.arg("-MMM")
+     .arg(self.get_tool_path(dep)?)
+     .arg(self.get_source_path(file)?)
+     .arg("-o")
+     .arg(self.get_tool_path(dst)?);
+
+    let mut outputs = vec![ dst, dep ];
+    outputs.extend(hdr);
+    self.run_cached(&t, file, dep, &outputs)
+  }
+
+  // Runs `t`, which compiles `file` to `outputs`, unless the hash stamped next
+  // to the first output shows the same command was already run on the same
+  // inputs: `file` and everything listed in its dependency file `dep`. Failing
+  // that, the outputs may come from the shared cache instead.
+  //
+  // Like ccache, the shared cache is keyed by the command and `file`, and
+  // records the dependencies seen last time so that a clean build, with no
+  // `dep` yet, can find the rest of the inputs to hash.
+  fn run_cached(&self, t: &Tool, file: &Path, dep: &Path, outputs: &[&Path]) -> Result<(), Error> {
+    let start = Instant::now();
+    let diags = self.run_or_reuse(t, file, dep, outputs)?;
+    self.timings.record(Timing {
+      file: file.to_path_buf(),
+      output: outputs[0].to_path_buf(),
+      targets: t.targets().to_string(),
+      start: Duration::default(),
+      duration: start.elapsed(),
+      compiled: diags.is_some(),
+      diagnostics: diags.map_or(vec![], |d| parse_diagnostics(&d)),
+      directory: match t.cwd {
+        Some(ref d) => d.clone(),
+        None        => std::env::current_dir()?,
+      },
+      command: Some(t.path.as_os_str()).into_iter().chain(t.args.iter().map(|a| &**a))
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect(),
+    }, start);
+    Ok(())
+  }
+
+  // The diagnostics of ispc, if it had to run, for `run_cached`.
+  fn run_or_reuse(&self, t: &Tool, file: &Path, dep: &Path, outputs: &[&Path])
+                  -> Result<Option<String>, Error> {
+    let mut stamp = outputs[0].as_os_str().to_owned();
+    stamp.push(".hash");
+    let stamp = PathBuf::from(stamp);
+
+    let key = if self.incremental { self.get_command_hash(t, file) } else { None };
+    let backend = if key.is_some() { self.get_cache_backend() } else { None };
+
+    if let Some(ref key) = key {
+      let deps = fs::read_to_string(dep).ok().or_else(|| {
+        backend.as_ref()
+          .and_then(|b| b.get(&format!("{}.deps", key)))
+          .and_then(|d| String::from_utf8(d).ok())
+      });
+      if let Some(hash) = deps.and_then(|d| self.get_input_hash(key, &d)) {
+        if outputs.iter().all(|o| o.exists()) &&
+           fs::read_to_string(&stamp).ok().is_some_and(|s| s == hash) {
+          return Ok(None);
+        }
+        if let Some(entry) = backend.as_ref().and_then(|b| b.get(&format!("{}.{}", key, hash))) {
+          if unpack_files(&self.get_out_dir()?, &entry).is_ok() && outputs.iter().all(|o| o.exists()) {
+            fs::write(&stamp, hash)?;
+            return Ok(None);
+          }
+        }
+      }
+    }
+
+    let _ = fs::remove_file(&stamp);
+    let diags = self.run_ispc(t)?;
+    if self.verify_determinism {
+      self.verify_outputs(t, file, outputs)?;
+    }
+
+    let key = match key {
+      Some(key) => key,
+      None      => return Ok(Some(diags)),
+    };
+    let deps = match fs::read_to_string(dep) {
+      Ok(deps) => deps,
+      Err(_)   => return Ok(Some(diags)),
+    };
+    if let Some(hash) = self.get_input_hash(&key, &deps) {
+      fs::write(&stamp, &hash)?;
+      if let Some(backend) = backend {
+        let mut files: Vec<PathBuf> = outputs.iter().map(|o| o.to_path_buf()).collect();
+        files.extend(self.get_target_outputs(outputs[0])?);
+        // A cache which can't be written to only costs time.
+        if let Ok(entry) = pack_files(&self.get_out_dir()?, &files) {
+          let _ = backend.put(&format!("{}.{}", key, hash), &entry)
+            .and_then(|_| backend.put(&format!("{}.deps", key), deps.as_bytes()));
+        }
+      }
+    }
+    Ok(Some(diags))
+  }
+
+  // Runs ispc again, to check that it writes the same `outputs` as it just did.
+  // See `verify_determinism`.
+  fn verify_outputs(&self, t: &Tool, file: &Path, outputs: &[&Path]) -> Result<(), Error> {
+    let mut files: Vec<PathBuf> = outputs.iter().map(|o| o.to_path_buf()).collect();
+    files.extend(self.get_target_outputs(outputs[0])?);
+    let hash = |f: &PathBuf| fs::read(f).ok().map(|b| {
+      let mut h = StableHasher::new();
+      b.hash(&mut h);
+      h.finish()
+    });
+
+    let first: Vec<Option<u64>> = files.iter().map(&hash).collect();
+    self.run_ispc(t)?;
+    let differing: Vec<PathBuf> =
+      files.iter().zip(first).filter(|&(f, h)| hash(f) != h).map(|(f, _)| f.clone()).collect();
+    if !differing.is_empty() {
+      return Err(Error::Nondeterministic { file: file.to_path_buf(), outputs: differing });
+    }
+    Ok(())
+  }
+
+  // Identifies the ispc command compiling `file`, less where it writes to, so
+  // that builds of other workspaces share it.
+  fn get_command_hash(&self, t: &Tool, file: &Path) -> Option<String> {
+    let out_dir = self.get_out_dir().ok()?;
+    let out_dir = out_dir.to_string_lossy();
+    let mut h = StableHasher::new();
+    self.get_ispc_version_string().ok()?.hash(&mut h);
+    for a in &t.args {
+      a.to_string_lossy().replace(&*out_dir, "$OUT_DIR").hash(&mut h);
+    }
+    t.envs.hash(&mut h);
+    self.current_dir.hash(&mut h);
+    fs::read(file).ok()?.hash(&mut h);
+    Some(format!("{:016x}", h.finish()))
+  }
+
+  // `None` if a dependency can't be read, such as one which has been deleted.
+  fn get_input_hash(&self, key: &str, deps: &str) -> Option<String> {
+    let mut h = StableHasher::new();
+    key.hash(&mut h);
+    for d in deps.lines() {
+      let d = self.get_dep_path(d).ok()?;
+      d.hash(&mut h);
+      fs::read(&d).ok()?.hash(&mut h);
+    }
+    Some(format!("{:016x}", h.finish()))
+  }
+
+  fn get_cache_backend(&self) -> Option<Arc<dyn CacheBackend>> {
+    let on = self.shared_cache.unwrap_or_else(|| {
+      self.cache_backend.0.is_some() || self.getenv("RISPC_CACHE_DIR").is_some()
+    });
+    if !on { return None; }
+    if let Some(ref b) = self.cache_backend.0 { return Some(b.clone()); }
+    self.get_shared_cache_dir().map(|d| Arc::new(DirCache::new(d)) as Arc<dyn CacheBackend>)
+  }
+
+  fn get_shared_cache_dir(&self) -> Option<PathBuf> {
+    if let Some(dir) = self.getenv("RISPC_CACHE_DIR") {
+      return if dir.is_empty() { None } else { Some(PathBuf::from(dir)) };
+    }
+    let base = if cfg!(windows) {
+      self.getenv("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+      self.getenv("HOME").map(|h| Path::new(&h).join("Library").join("Caches"))
+    } else {
+      self.getenv("XDG_CACHE_HOME").map(PathBuf::from)
+        .or_else(|| self.getenv("HOME").map(|h| Path::new(&h).join(".cache")))
+    };
+    base.map(|b| b.join("rispc"))
+  }
+
+  // ispc writes the code for each target of a multi-target build next to the
+  // dispatcher, with the target's suffix.
+  fn get_target_outputs(&self, out: &Path) -> Result<Vec<PathBuf>, Error> {
+    let targets = self.get_targets()?;
+    if targets.len() < 2 { return Ok(vec![]); }
+    let stem = out.file_stem().unwrap();
+    let ext = out.extension().map_or(String::new(), |e| e.to_string_lossy().into_owned());
+    Ok(targets.iter()
+      .map(|t| out.with_file_name(file_name(&target_stem(stem, &self.get_obj_suffix(t)), &ext)))
+      .collect())
+  }
+
+  // Which of the `objects` compiled from a file hold the code for each target,
+  // leaving out the dispatcher of a multi-target build.
+  fn get_target_objects(&self, objects: &[PathBuf]) -> Result<Vec<(Target, PathBuf)>, Error> {
+    let obj = match objects.first() { Some(o) => o, None => return Ok(vec![]) };
+    let targets = self.get_targets()?;
+    if targets.len() < 2 { return Ok(targets.into_iter().map(|t| (t, obj.clone())).collect()); }
+    Ok(targets.into_iter().zip(self.get_target_outputs(obj)?).collect())
+  }
+
+  // Prints the size of the code for each target of `libname`, warning of those
+  // over their `text_size_budget`.
+  fn check_text_sizes(&self, libname: &str, sizes: &[(Target, u64)]) {
+    for &(ref t, size) in sizes {
+      let name = self.get_spelling(t.to_str());
+      println!("text size of `{}` for {}: {} bytes", libname, name, size);
+      let budget = self.text_size_budgets.iter()
+        .filter(|b| self.get_obj_suffix(&b.0) == self.get_obj_suffix(t))
+        .map(|b| b.1)
+        .min();
+      if let Some(budget) = budget.filter(|&b| size > b) {
+        println!("cargo:warning=the {} code of `{}` is {} bytes, over its budget of {}",
+                 name, libname, size, budget);
+      }
+    }
+  }
+
+  /// Requires the installed ispc to satisfy `req`, such as `">=1.18"`. The
+  /// comparisons `>=`, `>`, `<=`, `<` and `=` are supported, and a bare
+  /// version means `>=`. May be given several times to bound the version from
+  /// both sides. `check` reports a requirement it can't read.
+  ///
+  /// Default value: any version is accepted
+  pub fn require_version(&mut self, req: &str) -> &mut Self {
+    self.required_versions.push(req.trim().to_string());
+    self
+  }
+
+  /// Finds out which version of ispc is installed.
+  pub fn ispc_version(&self) -> Version {
+    match self.get_ispc_version() {
+      Ok(v)  => v,
+      Err(e) => fail(&e.to_string()),
+    }
+  }
+
+  fn get_ispc_version(&self) -> Result<Version, Error> {
+    let line = self.get_ispc_version_string()?;
+    // Such as `Intel(r) Implicit SPMD Program Compiler (Intel(r) ISPC), 1.18.0 (build ...)`
+    line.split(|c: char| c.is_whitespace() || c == ',')
+      .filter_map(Version::parse)
+      .next()
+      .ok_or_else(|| Error::MissingCompiler {
+        path: PathBuf::from(self.getenv("ISPC").unwrap_or("ispc".into())),
+        reason: format!("could not find a version number in `{}`", line),
+      })
+  }
+
+  /// Checks that everything the build needs is in place: that ispc can be
+  /// run, what version it is, that it supports the architecture and targets
+  /// selected, and that `OUT_DIR` is writable.
+  ///
+  /// Every problem found is listed in the returned report, rather than just
+  /// the first. `compile` runs these checks itself before building anything.
+  pub fn check(&self) -> Result<(), Error> {
+    let mut problems = vec![];
+
+    match self.get_ispc_version() {
+      Ok(v)  => {
+        for req in &self.required_versions {
+          match parse_requirement(req) {
+            Some((op, r)) => {
+              let ok = match op {
+                ">=" => v >= r,
+                ">"  => v > r,
+                "<=" => v <= r,
+                "<"  => v < r,
+                _    => v == r,
+              };
+              if !ok {
+                problems.push(Error::UnsupportedVersion { found: v, required: format!("{}{}", op, r) });
+              }
+            },
+            None => problems.push(Error::InvalidConfig(
+                      format!("invalid ispc version requirement `{}`", req))),
+          }
+        }
+      },
+      Err(e) => problems.push(e),
+    }
+
+    if problems.is_empty() {
+      match self.getenv_unwrap("TARGET") {
+        Ok(ref triple) if !self.emit_spirv && self.architecture.is_none() &&
+                          self.get_triple_rule(triple, |r| r.arch).is_none() =>
+          problems.push(Error::UnsupportedArch { triple: triple.clone() }),
+        Ok(_) => if let Some(supported) = self.get_support_matrix() {
+          match self.get_host_targets() {
+            Ok(targets) => {
+              let missing: Vec<String> =
+                targets.iter()
+                  .map(|t| t.to_str())
+                  .filter(|t| !supported.iter().any(|s| is_spelling_of(s, t)))
+                  .map(String::from)
+                  .collect();
+              if !missing.is_empty() && (!self.filter_unsupported || missing.len() == targets.len()) {
+                problems.push(Error::UnsupportedTarget { targets: missing });
+              }
+            },
+            Err(e) => problems.push(e),
+          }
+        },
+        Err(e) => problems.push(e),
+      }
+    }
+
+    for &(ref from, ref to) in &self.path_remaps {
+      // As `remap_object` compares them.
+      let from_len = from.to_string_lossy().trim_end_matches(&['/', '\\'][..]).len();
+      let to_len = cmp::max(to.to_string_lossy().len(), 1);
+      if from_len == 0 || to_len > from_len {
+        problems.push(Error::InvalidConfig(
+          format!("can't remap `{}` to `{}`, which is longer", from.display(), to.display())));
+      }
+    }
+
+    match self.get_out_dir() {
+      Ok(out_dir) => {
+        let probe = out_dir.join(".rispc-check");
+        match fs::create_dir_all(&out_dir).and_then(|_| fs::File::create(&probe)) {
+          Ok(_)  => { let _ = fs::remove_file(&probe); },
+          Err(e) => problems.push(Error::InvalidConfig(
+                      format!("OUT_DIR `{}` is not writable: {}", out_dir.display(), e))),
+        }
+      },
+      Err(e) => problems.push(e),
+    }
+
+    if problems.is_empty() { Ok(()) } else { Err(Error::Check(problems)) }
+  }
+
+  // The first line of `ispc --version`, or what's wrong with the compiler.
+  fn get_ispc_version_string(&self) -> Result<String, Error> {
+    self.version.try_get(|| self.probe_ispc_version())
+  }
+
+  fn probe_ispc_version(&self) -> Result<String, Error> {
+    let mut t = self.get_base_compiler()?;
+    t.arg("--version");
+    let reason = match probe(&t) {
+      Ok(ref p) if p.status.success() => {
+        let out = &p.stdout;
+        match out.lines().find(|l| l.contains("ISPC") || l.contains("ispc")) {
+          Some(l) => return Ok(l.trim().to_string()),
+          None    => format!("it doesn't look like ispc, as `--version` printed `{}`", out.trim()),
+        }
+      },
+      Ok(p)  => format!("`--version` failed with {}", p.status),
+      Err(e) => e,
+    };
+    Err(Error::MissingCompiler { path: t.path, reason: reason })
+  }
+
+  /// Runs the compiler, generating the library `output`.
+  ///
+  /// The name `output` is that of the library, such as `mandelbrot`, which is
+  /// written out as `mandelbrot.lib` on MSVC and `libmandelbrot.a` elsewhere,
+  /// and linked to the crate being built. For compatibility, `libmandelbrot.a`
+  /// is accepted too.
+  ///
+  /// The build scripts of crates depending on the one being built, if it has a
+  /// `links` key, are told about the library through environment variables.
+  /// For `links = "mandelbrot"`, `DEP_MANDELBROT_LIB` is the library and
+  /// `DEP_MANDELBROT_ROOT` is `OUT_DIR`. Unless `emit_header` is off,
+  /// `DEP_MANDELBROT_HEADER` is the header and `DEP_MANDELBROT_INCLUDE` its
+  /// directory. `DEP_MANDELBROT_ISPC_INCLUDE` lists the `include` directories,
+  /// for ispc code of their own to use.
+  ///
+  /// How the library was compiled is written to `OUT_DIR/rispc_build_info.rs`,
+  /// for the crate to report, such as in bug reports:
+  ///
+  /// ```ignore
+  /// include!(concat!(env!("OUT_DIR"), "/rispc_build_info.rs"));
+  ///
+  /// println!("kernels built by {} for {:?}",
+  ///          rispc_build_info::mandelbrot::ISPC_VERSION,
+  ///          rispc_build_info::mandelbrot::TARGETS);
+  /// ```
+  ///
+  /// Along with `ISPC_VERSION` and `TARGETS`, there are `OPT_LEVEL` and
+  /// `DEFINES`, for each library the build script compiles. Characters of the
+  /// library's name which Rust doesn't allow in its module's become `_`, and
+  /// one is added before a leading digit, or after a keyword, such as `type_`.
+  ///
+  /// Returns the location of the generated header, unless `emit_header` or
+  /// `emit_spirv` says otherwise. Panics if anything goes wrong; see
+  /// `try_compile` to handle errors instead.
+  pub fn compile(&self, output: &str) -> Option<PathBuf> {
+    match self.try_compile(output) {
+      Ok(out) => out.header,
+      Err(e)  => fail(&e.to_string()),
+    }
+  }
+
+  /// Like `compile`, but returns what was built or any error, rather than
+  /// panicking.
+  pub fn try_compile(&self, output: &str) -> Result<CompileOutput, Error> {
+    self.build(output, false)
+  }
+
+  /// Runs the compiler, linking a shared library named like `output`, such as
+  /// `mandelbrot.dll`, `libmandelbrot.dylib` or `libmandelbrot.so`, for loading
+  /// at runtime.
+  ///
+  /// Only the functions declared `export` in ispc are visible from the library.
+  /// Nothing is linked to the crate being built. The header including the
+  /// headers of all files is still written, unless `emit_header` says
+  /// otherwise, but no Rust bindings are generated.
+  ///
+  /// Returns the location of the library. Panics if anything goes wrong; see
+  /// `try_compile_shared` to handle errors instead.
+  pub fn compile_shared(&self, output: &str) -> PathBuf {
+    match self.try_compile_shared(output) {
+      Ok(out) => out.archive.unwrap(),
+      Err(e)  => fail(&e.to_string()),
+    }
+  }
+
+  /// Like `compile_shared`, but returns what was built or any error, rather
+  /// than panicking.
+  pub fn try_compile_shared(&self, output: &str) -> Result<CompileOutput, Error> {
+    self.build(output, true)
+  }
+
+  /// Combines the static libraries and object files `inputs` into the library
+  /// `output`, named as for `compile`, which is linked to the crate being
+  /// built. Members which share a name are all kept.
+  ///
+  /// Only the archiver settings and `out_dir` of this `Config` are used.
+  pub fn try_merge_libraries<P: AsRef<Path>>(&self, output: &str, inputs: &[P])
+                                            -> Result<CompileOutput, Error> {
+    let libname = get_lib_name(output)?;
+    let dst = self.get_out_dir()?;
+    let dir = self.get_intermediate_dir(libname)?.join("merged");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir)?;
+
+    // Each member gets a name of its own, or the archiver would replace
+    // members with others of the same name.
+    let mut objects = vec![];
+    for (i, input) in inputs.iter().enumerate() {
+      let input = input.as_ref();
+      if self.cargo_metadata { println!("cargo:rerun-if-changed={}", input.display()); }
+      let members = match read_archive(input)? {
+        Some(members) => members,
+        None          => {
+          let name = input.file_name().map_or("object".into(), |n| n.to_string_lossy().into_owned());
+          vec![ (name, fs::read(input)?) ]
+        },
+      };
+      for (j, (name, data)) in members.into_iter().enumerate() {
+        let obj = dir.join(format!("{}_{}_{}", i, j, name));
+        fs::write(&obj, data)?;
+        objects.push(obj);
+      }
+    }
+
+    let lib = dst.join(self.get_archive_name(libname)?);
+    if let Err(e) = self.archive(&lib, &objects) {
+      return Err(Error::Archive { output: output.into(), message: e.to_string() });
+    }
+    if self.cargo_metadata {
+      println!("cargo:rustc-link-lib=static={}", libname);
+      println!("cargo:rustc-link-search=native={}", dst.display());
+    }
+    self.print_metadata(&lib, None)?;
+    Ok(CompileOutput { archive: Some(lib), objects: objects, header: None,
+                       timings: vec![], variants: vec![], text_sizes: vec![] })
+  }
+
+  // Adds how `libname` is compiled to `rispc_build_info.rs`, which describes
+  // every library this build script has compiled.
+  fn write_build_info(&self, libname: &str) -> Result<(), Error> {
+    static LIBS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+    let name = rust_ident(libname);
+    let targets: Vec<String> =
+      self.get_targets()?.iter().map(|t| self.get_spelling(t.to_str())).collect();
+    let mut info = String::new();
+    info.push_str(&format!("  pub mod {} {{\n", name));
+    info.push_str(&format!("    pub const ISPC_VERSION: &str = {:?};\n",
+                           self.get_ispc_version_string()?));
+    info.push_str(&format!("    pub const TARGETS: &[&str] = &{:?};\n", targets));
+    info.push_str(&format!("    pub const OPT_LEVEL: u32 = {};\n", self.get_opt_level()?));
+    info.push_str(&format!("    pub const DEFINES: &[(&str, Option<&str>)] = &{:?};\n",
+                           self.get_defs()?));
+    info.push_str("  }\n");
+
+    let mut libs = LIBS.lock().unwrap();
+    libs.retain(|&(ref n, _)| *n != name);
+    libs.push((name, info));
+    let mut out = String::from("/// How the ispc code of each library was compiled.\n\
+                                #[allow(dead_code)]\npub mod rispc_build_info {\n");
+    for &(_, ref info) in libs.iter() { out.push_str(info); }
+    out.push_str("}\n");
+    let dst = self.get_out_dir()?;
+    fs::create_dir_all(&dst)?;
+    fs::write(dst.join("rispc_build_info.rs"), out)?;
+    Ok(())
+  }
+
+  // Tells the build scripts of dependent crates where to find what was built.
+  // See `compile`.
+  fn print_metadata(&self, lib: &Path, header: Option<&Path>) -> Result<(), Error> {
+    println!("cargo:root={}", self.get_out_dir()?.display());
+    println!("cargo:lib={}", lib.display());
+    if let Some(h) = header {
+      println!("cargo:header={}", h.display());
+      println!("cargo:include={}", h.parent().unwrap().display());
+    }
+    if !self.includes.is_empty() {
+      let cwd = std::env::current_dir()?;
+      if let Ok(dirs) = std::env::join_paths(self.includes.iter().map(|i| cwd.join(i))) {
+        println!("cargo:ispc_include={}", dirs.to_string_lossy());
+      }
+    }
+    Ok(())
+  }
+
+  fn build(&self, output: &str, shared: bool) -> Result<CompileOutput, Error> {
+    let mut out = self.build_one(output, shared)?;
+    let outbase = get_lib_name(output)?;
+    for &(ref name, ref f) in &self.variants {
+      if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(Error::InvalidConfig(format!("`{}` is not a variant name, such as `double`",
+                                                name)));
+      }
+      if out.variant(name).is_some() {
+        return Err(Error::InvalidConfig(format!("the variant `{}` was added twice", name)));
+      }
+      let v = self.get_variant(name, f);
+      let vout = v.build_one(&format!("{}_{}", outbase, name), shared)?;
+      out.variants.push((name.clone(), vout));
+    }
+    Ok(out)
+  }
+
+  // This `Config` as changed for the variant `name`, whose outputs must not
+  // overwrite those of this one.
+  fn get_variant(&self, name: &str, f: &Callbacks<VariantFn>) -> Config {
+    let suffixed = |p: &Path| {
+      let mut file = p.file_stem().unwrap_or_default().to_os_string();
+      file.push(format!("_{}", name));
+      if let Some(ext) = p.extension() { file.push("."); file.push(ext); }
+      p.with_file_name(file)
+    };
+    let mut v = self.clone();
+    v.variants = vec![];
+    v.variant = Some(name.into());
+    v.intermediate_dir = self.intermediate_dir.as_ref().map(|d| d.join(name));
+    v.header_path = self.header_path.as_ref().map(|p| suffixed(p));
+    v.report_path = self.report_path.as_ref().map(|p| suffixed(p));
+    for f in &f.0 { f(&mut v); }
+    v
+  }
+
+  fn build_one(&self, output: &str, shared: bool) -> Result<CompileOutput, Error> {
+    // Declared whenever they might be set, so rustc doesn't warn about checking
+    // them.
+    if self.skip_if_unavailable {
+      println!("cargo:rustc-check-cfg=cfg(rispc_skipped)");
+    }
+    if self.allow_missing_compiler {
+      println!("cargo:rustc-check-cfg=cfg(rispc_unavailable)");
+    }
+    if self.get_skip() {
+      return self.skip(get_lib_name(output)?, shared);
+    }
+    if self.allow_missing_compiler {
+      if let Err(e @ Error::MissingCompiler { .. }) = self.get_ispc_version() {
+        println!("cargo:warning={}", e);
+        println!("cargo:rustc-cfg=rispc_unavailable");
+        return self.nothing_built(get_lib_name(output)?, shared);
+      }
+    }
+    self.timings.start();
+    let mut out = self.build_library(output, shared)?;
+    out.timings = self.timings.take();
+    if self.trace {
+      let path = self.get_out_dir()?.join(format!("rispc_trace_{}.json", get_lib_name(output)?));
+      fs::write(path, trace_json(&out.timings))?;
+    }
+    if let Some(ref p) = self.report_path {
+      self.write_report(&self.get_out_dir()?.join(p), get_lib_name(output)?, &out)?;
+    }
+    if let Some(ref p) = self.compile_commands {
+      write_compile_commands(&self.get_out_dir()?.join(p), get_lib_name(output)?, &out.timings)?;
+    }
+    Ok(out)
+  }
+
+  // See `report_path`.
+  fn write_report(&self, path: &Path, libname: &str, out: &CompileOutput) -> Result<(), Error> {
+    let list = |items: Vec<String>| format!("[{}]", items.join(","));
+    let path_str = |p: &Path| json_str(&p.to_string_lossy());
+
+    let targets =
+      self.get_targets()?.iter().map(|t| json_str(&self.get_spelling(t.to_str()))).collect();
+    let flags = self.basic_tool()?.args.iter().map(|a| json_str(&a.to_string_lossy())).collect();
+    let mut objects = vec![];
+    for o in &out.objects {
+      objects.push(format!("{{\"path\":{},\"size\":{}}}", path_str(o), fs::metadata(o)?.len()));
+    }
+    let files: Vec<String> = out.timings.iter().map(|t| format!(
+      "{{\"file\":{},\"output\":{},\"targets\":{},\"start_us\":{},\"duration_us\":{},\
+       \"cached\":{},\"warnings\":{}}}",
+      path_str(&t.file), path_str(&t.output), json_str(&t.targets), t.start.as_micros(),
+      t.duration.as_micros(), !t.compiled, list(t.diagnostics.iter().map(|d| json_str(&d.to_string())).collect())))
+      .collect();
+    let duration = out.timings.iter().map(|t| t.start + t.duration).max().unwrap_or_default();
+
+    let mut report = String::from("{\n");
+    report.push_str(&format!("  \"library\": {},\n", json_str(libname)));
+    report.push_str(&format!("  \"archive\": {},\n",
+                             out.archive().map(&path_str).unwrap_or("null".into())));
+    report.push_str(&format!("  \"header\": {},\n",
+                             out.header().map(path_str).unwrap_or("null".into())));
+    report.push_str(&format!("  \"ispc_version\": {},\n",
+                             json_str(&self.get_ispc_version_string()?)));
+    report.push_str(&format!("  \"targets\": {},\n", list(targets)));
+    report.push_str(&format!("  \"flags\": {},\n", list(flags)));
+    report.push_str(&format!("  \"duration_us\": {},\n", duration.as_micros()));
+    report.push_str(&format!("  \"cache_hits\": {},\n",
+                             out.timings.iter().filter(|t| !t.compiled).count()));
+    let sizes: Vec<String> = out.text_sizes.iter().map(|&(ref t, size)| {
+      format!("{{\"target\":{},\"bytes\":{}}}", json_str(&self.get_spelling(t.to_str())), size)
+    }).collect();
+    report.push_str(&format!("  \"text_sizes\": {},\n", list(sizes)));
+    report.push_str(&format!("  \"files\": [\n    {}\n  ],\n", files.join(",\n    ")));
+    report.push_str(&format!("  \"objects\": [\n    {}\n  ]\n", objects.join(",\n    ")));
+    report.push_str("}\n");
+
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, report)?;
+    Ok(())
+  }
+
+  fn get_skip(&self) -> bool {
+    self.skip_if_unavailable &&
+      (self.getenv("DOCS_RS").is_some() || self.getenv("RISPC_SKIP").is_some_and(|v| v == "1"))
+  }
+
+  // See `skip_if_unavailable`.
+  fn skip(&self, outbase: &str, shared: bool) -> Result<CompileOutput, Error> {
+    println!("cargo:rustc-cfg=rispc_skipped");
+
+    if let Some(ref p) = self.pregenerated_bindings {
+      let dst = self.get_out_dir()?;
+      println!("cargo:rerun-if-changed={}", p.display());
+      fs::create_dir_all(&dst)?;
+      fs::copy(p, dst.join(outbase).with_extension("rs"))?;
+    }
+    self.nothing_built(outbase, shared)
+  }
+
+  // What `build` returns when skipping everything. `compile_shared` still
+  // needs a path.
+  fn nothing_built(&self, outbase: &str, shared: bool) -> Result<CompileOutput, Error> {
+    let archive =
+      if shared { Some(self.get_out_dir()?.join(self.get_shared_name(outbase)?)) } else { None };
+    Ok(CompileOutput { archive: archive, objects: vec![], header: None,
+                       timings: vec![], variants: vec![], text_sizes: vec![] })
+  }
+
+  fn build_library(&self, output: &str, shared: bool) -> Result<CompileOutput, Error> {
+    let outbase = get_lib_name(output)?;
+
+    if shared && self.emit_spirv {
+      return Err(Error::InvalidConfig("SPIR-V can't be linked into a shared library".into()));
+    }
+    if self.rust_dispatch && (shared || !self.emit_header || self.emit_spirv) {
+      return Err(Error::InvalidConfig("rust_dispatch needs Rust bindings to dispatch from".into()));
+    }
+
+    self.check()?;
+
+    let dst = self.get_out_dir()?;
+    let int_dir = self.get_intermediate_dir(outbase)?;
+
+    for i in &self.includes {
+      println!("cargo:rerun-if-changed={}", i.display());
+    }
+
+    self.write_build_info(outbase)?;
+
+    if self.emit_spirv {
+      let objects = self.compile_spirv(outbase, &int_dir)?;
+      return Ok(CompileOutput { archive: None, objects: objects, header: None,
+                                timings: vec![], variants: vec![], text_sizes: vec![] });
+    }
+
+    // The headers list what a shared library exports, what isn't hidden, or
+    // what's renamed.
+    let emit_hdr = self.emit_header || shared || self.symbol_prefix.is_some() ||
+                   self.variant.is_some() || !self.specializations.is_empty() ||
+                   (self.hide_symbols && !self.is_msvc()?);
+
+    // Each specialization is compiled into its own directory, and renamed apart
+    // from the others once all are compiled.
+    let specs = self.get_specializations()?;
+    let mut configs = vec![];
+    let mut dirs = vec![];
+    for &(ref suffix, ref defs, ref target) in &specs {
+      let mut cfg = self.clone();
+      for &(ref k, ref v) in defs { cfg.define(k, Some(v)); }
+      // Or the internal functions of the copies clash.
+      if !suffix.is_empty() || self.variant.is_some() { cfg.hide_symbols = true; }
+      if let Some(ref t) = *target {
+        cfg.targets = Some(vec![t.clone()]);
+        cfg.supported_targets = Cache::new();
+      }
+      configs.push(cfg);
+      dirs.push(if suffix.is_empty() { int_dir.clone() } else { int_dir.join(&suffix[1..]) });
+    }
+    let work: Vec<(usize, &PathBuf)> =
+      (0..specs.len()).flat_map(|i| self.files.iter().map(move |f| (i, f))).collect();
+    let compiled: Result<Vec<(usize, Compiled)>, Error> = parallel_map(self.get_jobs(), &work, |&(i, f)| {
+      configs[i].compile_file(f, &dirs[i], emit_hdr).map(|c| (i, c))
+    }).into_iter().collect();
+
+    let mut objects = Vec::new();
+    let mut sources = Vec::new();
+    let mut headers = Vec::new();
+    let mut knc = false;
+    let mut math_libs = vec![];
+
+    let mut deps = vec![];
+
+    // The headers, objects and C++ sources of each specialization.
+    let mut spec_files: Vec<(Vec<PathBuf>, Vec<PathBuf>, Vec<PathBuf>)> =
+      vec![(vec![], vec![], vec![]); specs.len()];
+    let mut text_sizes: Vec<(Target, u64)> = vec![];
+    for (i, f) in compiled? {
+      for (t, o) in configs[i].get_target_objects(&f.objects)? {
+        let size = match text_size(&o)? { Some(size) => size, None => continue };
+        match text_sizes.iter_mut().find(|s| s.0 == t) {
+          Some(s) => s.1 += size,
+          None    => text_sizes.push((t, size)),
+        }
+      }
+      let (ref mut spec_headers, ref mut spec_objects, ref mut spec_sources) = spec_files[i];
+      for o in f.objects {
+        if !spec_objects.contains(&o) { spec_objects.push(o); }
+      }
+      spec_sources.extend(f.cpp.clone());
+      sources.extend(f.cpp);
+      spec_headers.push(f.header);
+      deps.extend(f.deps);
+      knc |= f.knc;
+      if !math_libs.contains(&f.math_lib) { math_libs.push(f.math_lib); }
+    }
+
+    self.check_text_sizes(outbase, &text_sizes);
+
+    let mut c = cc::Build::new();
+    c.out_dir(&dst).cargo_metadata(self.cargo_metadata);
+    if let Some(ar) = self.get_archiver()? { c.archiver(ar); }
+    for f in &self.ar_flags { c.ar_flag(f); }
+    c.static_crt(self.get_static_crt()?);
+    let cwd = std::env::current_dir()?;
+    if !sources.is_empty() {
+      if knc {
+        if self.cxx_include_file.is_none() {
+          return Err(Error::InvalidConfig("Target::Knc requires cxx_include_file to be set to \
+                                           ispc's knc.h".into()));
+        }
+        c.flag("-mmic");
+      }
+      c.cpp(true);
+      for &(ref from, ref to) in self.path_remaps.iter().rev() {
+        c.flag_if_supported(format!("-fdebug-prefix-map={}={}", from.display(), to.display()));
+      }
+      if let Some(dir) = self.cxx_include_file.as_ref().and_then(|i| i.parent()) {
+        c.include(native_path(dir, &cwd));
+      }
+    }
+    let prefix = self.symbol_prefix.as_ref().map_or("", |p| &**p);
+    let variant = self.variant.as_ref().map_or(String::new(), |v| format!("_{}", v));
+    for (i, (spec_headers, mut spec_objects, spec_sources)) in spec_files.into_iter().enumerate() {
+      spec_objects.extend(self.compile_cpp(&c, &spec_sources)?);
+      let suffix = format!("{}{}", specs[i].0, variant);
+      if self.symbol_prefix.is_some() || !suffix.is_empty() {
+        self.rename_symbols(prefix, &suffix, &dirs[i], &spec_headers, &spec_objects)?;
+      }
+      objects.extend(spec_objects);
+      headers.extend(spec_headers);
+    }
+    if self.strip_debug {
+      self.objcopy("--strip-debug", None, &objects)?;
+    }
+    for f in &self.post_process.0 {
+      f(&objects)?;
+    }
+    for o in &self.objects {
+      println!("cargo:rerun-if-changed={}", o.display());
+      if !objects.contains(o) { objects.push(o.clone()); }
+    }
+    for o in &objects { c.object(native_path(o, &cwd)); }
+
+    let archive = if shared {
+      self.link_shared(&c, outbase, &objects, &headers, &math_libs)?
+    } else {
+      let lib = dst.join(self.get_archive_name(outbase)?);
+      if self.thin_archive {
+        // Which the `cc` crate can't do.
+        if let Err(e) = self.archive(&lib, &objects) {
+          return Err(Error::Archive { output: output.into(), message: e.to_string() });
+        }
+        if self.cargo_metadata {
+          println!("cargo:rustc-link-lib=static={}", outbase);
+          println!("cargo:rustc-link-search=native={}", dst.display());
+          if !sources.is_empty() { self.print_cpp_links()?; }
+        }
+      } else if let Err(e) = c.try_compile(outbase) {
+        return Err(Error::Archive { output: output.into(), message: e.to_string() });
+      }
+      if self.cargo_metadata { self.print_math_links(&math_libs)?; }
+      lib
+    };
+    let archive = Some(archive);
+
+    print_deps(&deps);
+
+    let superheader =
+      match self.header_path {
+        Some(ref p) => dst.join(p),
+        None        => dst.join(outbase).with_extension("h"),
+      };
+    // Those of variants would replace those of the library itself.
+    if self.variant.is_none() {
+      self.print_metadata(archive.as_ref().unwrap(),
+                          if self.emit_header { Some(&superheader) } else { None })?;
+    }
+
+    if !self.emit_header {
+      return Ok(CompileOutput { archive: archive, objects: objects, header: None,
+                                timings: vec![], variants: vec![], text_sizes: text_sizes });
+    }
+
+    {
+      fs::create_dir_all(superheader.parent().unwrap())?;
+      let mut bindgen_f = fs::File::create(superheader.clone())?;
+      for h in headers {
+        writeln!(bindgen_f, "#include \"{}\"", h.display())?;
+      }
+    }
+
+    if shared {
+      return Ok(CompileOutput { archive: archive, objects: objects, header: Some(superheader),
+                                timings: vec![], variants: vec![], text_sizes: text_sizes });
+    }
+
+    let bindgen_dst = dst.join(outbase).with_extension("rs");
+
+    let header = match superheader.to_str() {
+      Some(h) => h.to_string(),
+      None    => return Err(Error::Bindings { header: superheader }),
+    };
+    let bindings =
+      bindgen::builder()
+        .emit_builtins()
+        .forbid_unknown_types()
+        .header(&header)
+        .link_static(outbase)
+        .generate();
+    match bindings {
+      Ok(b)  => b.write_to_file(&bindgen_dst)?,
+      Err(_) => return Err(Error::Bindings { header: superheader }),
+    }
+
+    if self.get_vectorcall()? {
+      let mut bindings = String::new();
+      fs::File::open(&bindgen_dst)?.read_to_string(&mut bindings)?;
+      let bindings = bindings.replace("extern \"C\"", "extern \"vectorcall\"");
+      fs::File::create(&bindgen_dst)?.write_all(bindings.as_bytes())?;
+    }
+
+    if self.rust_dispatch {
+      let bindings = fs::read_to_string(&bindgen_dst)?;
+      let dispatch = self.get_rust_dispatch(&bindings)?;
+      fs::write(&bindgen_dst, bindings + &dispatch)?;
+    }
+
+    Ok(CompileOutput { archive: archive, objects: objects, header: Some(superheader),
+                       timings: vec![], variants: vec![], text_sizes: text_sizes })
+  }
+
+  // The Rust functions `rust_dispatch` adds to the `bindings`, each calling
+  // whichever of the functions of the same name suffixed with a target the CPU
+  // supports best, unless `ispc_select_target` picked another.
+  fn get_rust_dispatch(&self, bindings: &str) -> Result<String, Error> {
+    let mut targets = self.get_targets()?;
+    targets.sort_by_key(|t| cmp::Reverse(t.isa_level()));
+    let suffixes: Vec<String> = targets.iter().map(|t| format!("_{}", self.get_obj_suffix(t))).collect();
+    let fns = get_rust_fns(bindings);
+    let atomic = "::std::sync::atomic";
+
+    let mut out = String::from("\n// Dispatches between the targets `rust_dispatch` compiled for on their own.\n\n");
+    let names: Vec<String> = suffixes.iter().map(|s| format!("{:?}", &s[1..])).collect();
+    out.push_str("/// The targets the functions without a target suffix dispatch between, most\n");
+    out.push_str("/// capable first.\n");
+    out.push_str(&format!("pub const ISPC_TARGETS: &'static [&'static str] = &[{}];\n\n",
+                          names.join(", ")));
+    out.push_str(&format!("static ISPC_TARGET: {a}::AtomicUsize = {a}::AtomicUsize::new(!0);\n\n",
+                          a = atomic));
+    out.push_str("/// Whether this CPU supports the target `ISPC_TARGETS[i]`.\n");
+    out.push_str("pub fn ispc_target_supported(i: usize) -> bool {\n    match i {\n");
+    for (i, t) in targets.iter().enumerate() {
+      let checks: Vec<String> = t.features().unwrap().iter()
+        .map(|f| format!("::std::is_x86_feature_detected!({:?})", f))
+        .collect();
+      out.push_str(&format!("        {} => {},\n", i, checks.join(" && ")));
+    }
+    out.push_str("        _ => false,\n    }\n}\n\n");
+    out.push_str("/// Makes the functions dispatch to `target`, one of `ISPC_TARGETS`, rather\n");
+    out.push_str("/// than to the most capable one this CPU supports. Returns `false`, changing\n");
+    out.push_str("/// nothing, if this CPU doesn't support it.\n");
+    out.push_str("pub fn ispc_select_target(target: &str) -> bool {\n");
+    out.push_str("    match ISPC_TARGETS.iter().position(|&t| t == target) {\n");
+    out.push_str("        Some(i) if ispc_target_supported(i) => {\n");
+    out.push_str(&format!("            ISPC_TARGET.store(i, {}::Ordering::Relaxed);\n", atomic));
+    out.push_str("            true\n        }\n        _ => false,\n    }\n}\n\n");
+    out.push_str("/// The target the functions dispatch to, as an index into `ISPC_TARGETS`.\n");
+    out.push_str("pub fn ispc_target() -> usize {\n");
+    out.push_str(&format!("    let i = ISPC_TARGET.load({}::Ordering::Relaxed);\n", atomic));
+    out.push_str("    if i != !0 { return i; }\n");
+    out.push_str("    let i = (0..ISPC_TARGETS.len()).find(|&i| ispc_target_supported(i))\n");
+    out.push_str("        .expect(\"this CPU supports none of the targets ispc compiled for\");\n");
+    out.push_str(&format!("    ISPC_TARGET.store(i, {}::Ordering::Relaxed);\n    i\n}}\n", atomic));
+
+    for f in fns.iter().filter(|f| f.name.ends_with(&*suffixes[0])) {
+      let name = &f.name[..f.name.len() - suffixes[0].len()];
+      let all = suffixes.iter().all(|s| fns.iter().any(|g| g.name == format!("{}{}", name, s)));
+      if !all { continue; }
+      let args = f.args.join(", ");
+      out.push_str(&format!("\n/// Calls whichever of the `{}_*` functions `ispc_target` picks.\n", name));
+      out.push_str(&format!("pub unsafe fn {}({}){} {{\n    match ispc_target() {{\n",
+                            name, f.params, f.ret));
+      for (i, s) in suffixes.iter().enumerate() {
+        let arm = if i + 1 == suffixes.len() { "_".to_string() } else { i.to_string() };
+        out.push_str(&format!("        {} => {}{}({}),\n", arm, name, s, args));
+      }
+      out.push_str("    }\n}\n");
+    }
+    Ok(out)
+  }
+
+  // Makes every symbol defined in `objects` local, except for those declared in
+  // `hdr` and their variants for each target, which ispc names `<name>_<isa>`
+  // and the dispatcher in another object calls.
+  fn hide_symbols(&self, hdr: &Path, objects: &[PathBuf]) -> Result<(), Error> {
+    let prefix = if self.getenv_unwrap("TARGET")?.contains("apple") { "_" } else { "" };
+    let targets = self.get_targets()?;
+    let mut keep = String::new();
+    for e in get_exports(&fs::read_to_string(hdr)?) {
+      keep.push_str(&format!("{}{}\n", prefix, e));
+      if targets.len() < 2 { continue; }
+      for t in &targets {
+        keep.push_str(&format!("{}{}_{}\n", prefix, e, self.get_obj_suffix(t)));
+      }
+    }
+    let list = hdr.with_extension("keep");
+    fs::write(&list, keep)?;
+    self.objcopy("--keep-global-symbols=", Some(&list), objects)
+  }
+
+  // Renames the functions declared in `headers` to begin with `prefix` and end
+  // with `suffix`, along with their variants for each target, in `objects` and
+  // the `headers` themselves. What already begins and ends so is left alone, as
+  // it is once renamed, for when ispc didn't need to run again.
+  fn rename_symbols(&self, prefix: &str, suffix: &str, int_dir: &Path, headers: &[PathBuf],
+                    objects: &[PathBuf]) -> Result<(), Error> {
+    let mangle = if self.getenv_unwrap("TARGET")?.contains("apple") { "_" } else { "" };
+    let targets = self.get_targets()?;
+    let mut renames = String::new();
+    for h in headers {
+      let text = fs::read_to_string(h)?;
+      let exports: Vec<String> =
+        get_exports(&text).into_iter()
+          .filter(|e| !(e.starts_with(prefix) && e.ends_with(suffix)))
+          .collect();
+      if exports.is_empty() { continue; }
+      for e in &exports {
+        renames.push_str(&format!("{m}{e} {m}{p}{e}{x}\n",
+                                  m = mangle, p = prefix, e = e, x = suffix));
+        if targets.len() < 2 { continue; }
+        for t in &targets {
+          renames.push_str(&format!("{m}{e}_{s} {m}{p}{e}{x}_{s}\n",
+                                    m = mangle, p = prefix, e = e, x = suffix,
+                                    s = self.get_obj_suffix(t)));
+        }
+      }
+      let text: Vec<String> = text.lines().map(|l| {
+        match get_exports(l).first() {
+          Some(e) if exports.contains(e) => {
+            match l.find(&format!("{}(", e)) {
+              Some(at) => format!("{}{}{}{}{}", &l[..at], prefix, e, suffix, &l[at + e.len()..]),
+              None     => l.to_string(),
+            }
+          },
+          _ => l.to_string(),
+        }
+      }).collect();
+      fs::write(h, text.join("\n") + "\n")?;
+    }
+    if renames.is_empty() { return Ok(()); }
+    let list = int_dir.join("symbols.redefine");
+    fs::write(&list, renames)?;
+    self.objcopy("--redefine-syms=", Some(&list), objects)
+  }
+
+  // Runs `objcopy` on each of `objects`, passing it `flag`, followed by any
+  // `list` it names.
+  fn objcopy(&self, flag: &str, list: Option<&Path>, objects: &[PathBuf]) -> Result<(), Error> {
+    let objcopy = self.getenv("OBJCOPY").unwrap_or_else(|| "objcopy".to_string());
+    let cwd = std::env::current_dir()?;
+    for o in objects {
+      let mut arg = OsString::from(flag);
+      if let Some(list) = list { arg.push(native_path(list, &cwd)); }
+      let mut t = Tool::new(PathBuf::from(&objcopy));
+      t.arg(arg).arg(native_path(o, &cwd));
+      run(&t, self.get_verbosity(), &mut |_| true)?;
+    }
+    Ok(())
+  }
+
+  // Archives `objects` into a thin archive `lib`, with `ar_flags`.
+  fn archive(&self, lib: &Path, objects: &[PathBuf]) -> Result<(), Error> {
+    let target = self.getenv_unwrap("TARGET")?;
+    let msvc = target.contains("msvc");
+    let ar = match self.get_archiver()? {
+      Some(ar) => Some(ar),
+      None     => self.get_archiver_env()?,
+    };
+    let mut t = match (ar, cc::windows_registry::find(&target, "lib.exe")) {
+      (Some(ar), _)                 => Tool::new(ar),
+      (None, Some(ref cmd)) if msvc => {
+        let mut t = Tool::new(PathBuf::from(cmd.get_program()));
+        t.envs = cmd.get_envs()
+                    .filter_map(|(k, v)| v.map(|v| (k.to_owned(), v.to_owned())))
+                    .collect();
+        t
+      },
+      (None, _)                     => Tool::new(PathBuf::from(if msvc { "lib.exe" } else { "ar" })),
+    };
+    let cwd = std::env::current_dir()?;
+
+    // `ar` adds to an existing archive, rather than replacing it.
+    let _ = fs::remove_file(lib);
+
+    if msvc {
+      let mut out = OsString::from("/OUT:");
+      out.push(native_path(lib, &cwd));
+      t.arg(out).arg("/nologo");
+      for f in &self.ar_flags { t.arg(f); }
+    } else {
+      for f in &self.ar_flags { t.arg(f); }
+      t.arg(if self.thin_archive { "crsT" } else { "crs" }).arg(native_path(lib, &cwd));
+    }
+    for o in objects { t.arg(native_path(o, &cwd)); }
+    run(&t, self.get_verbosity(), &mut |_| true)?;
+    Ok(())
+  }
+
+  // The C++ standard library, for archives holding objects compiled from C++.
+  fn print_cpp_links(&self) -> Result<(), Error> {
+    let target = self.getenv_unwrap("TARGET")?;
+    if target.contains("msvc") { return Ok(()); }
+    println!("cargo:rustc-link-lib={}", if target.contains("apple") { "c++" } else { "stdc++" });
+    Ok(())
+  }
+
+  // Compiles the C++ `sources` written for `Generic_*` targets with the C++
+  // compiler `c`, each to an object next to it.
+  fn compile_cpp(&self, c: &cc::Build, sources: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let msvc = self.getenv_unwrap("TARGET")?.contains("msvc");
+    let cwd = std::env::current_dir()?;
+    let mut objects = vec![];
+    for s in sources {
+      let obj = s.with_extension(if msvc { "obj" } else { "o" });
+      let mut t = cc_tool(c);
+      if msvc {
+        let mut out = OsString::from("/Fo");
+        out.push(native_path(&obj, &cwd));
+        t.arg("/c").arg(native_path(s, &cwd)).arg(out);
+      } else {
+        t.arg("-c").arg(native_path(s, &cwd)).arg("-o").arg(native_path(&obj, &cwd));
+      }
+      run(&t, self.get_verbosity(), &mut |_| true)?;
+      objects.push(obj);
+    }
+    Ok(objects)
+  }
+
+  // Links `objects` into the shared library `libname` with the compiler `c`,
+  // returning where it is. Exports are limited to the functions declared in the
+  // ispc `headers`, except on Windows, where `dll_export` marks them.
+  fn link_shared(&self, c: &cc::Build, libname: &str, objects: &[PathBuf],
+                 headers: &[PathBuf], math_libs: &[Math]) -> Result<PathBuf, Error> {
+    let lib = self.get_out_dir()?.join(self.get_shared_name(libname)?);
+    let int_dir = self.get_intermediate_dir(libname)?;
+    let target = self.getenv_unwrap("TARGET")?;
+    let msvc = target.contains("msvc");
+    let cwd = std::env::current_dir()?;
+
+    let mut exports = vec![];
+    for h in headers {
+      exports.extend(get_exports(&fs::read_to_string(h)?));
+    }
+
+    let mut t = cc_tool(c);
+    if msvc {
+      let mut out = OsString::from("/Fe");
+      out.push(native_path(&lib, &cwd));
+      t.arg("/LD").arg(out);
+      for o in objects { t.arg(native_path(o, &cwd)); }
+    } else {
+      t.arg("-shared").arg("-o").arg(native_path(&lib, &cwd));
+      for o in objects { t.arg(native_path(o, &cwd)); }
+
+      if target.contains("apple") {
+        let list = int_dir.join("exports.txt");
+        let names: Vec<String> = exports.iter().map(|e| format!("_{}\n", e)).collect();
+        fs::write(&list, names.concat())?;
+        let mut arg = OsString::from("-Wl,-exported_symbols_list,");
+        arg.push(native_path(&list, &cwd));
+        t.arg(arg);
+      } else if !self.get_target_windows()? {
+        let script = int_dir.join("exports.map");
+        let names: Vec<String> = exports.iter().map(|e| format!("    {};\n", e)).collect();
+        fs::write(&script, format!("{{\n  global:\n{}  local:\n    *;\n}};\n", names.concat()))?;
+        let mut arg = OsString::from("-Wl,--version-script=");
+        arg.push(native_path(&script, &cwd));
+        t.arg(arg);
+      }
+
+      if math_libs.contains(&Math::System) && !self.get_target_windows()? {
+        t.arg("-lm");
+      }
+      if math_libs.contains(&Math::Svml) {
+        if let Some(ref p) = self.svml_path {
+          t.arg("-L").arg(native_path(p, &cwd));
+        }
+        t.arg(if self.get_target_windows()? { "-lsvml_dispmt" } else { "-lsvml" });
+      }
+    }
+    if msvc && math_libs.contains(&Math::Svml) {
+      t.arg("/link");
+      if let Some(ref p) = self.svml_path {
+        let mut arg = OsString::from("/LIBPATH:");
+        arg.push(native_path(p, &cwd));
+        t.arg(arg);
+      }
+      t.arg("svml_dispmt.lib");
+    }
+    if let Err(e) = run(&t, self.get_verbosity(), &mut |_| true) {
+      return Err(Error::Link { output: lib.display().to_string(), message: e.to_string() });
+    }
+    Ok(lib)
+  }
+
+  // The stem of `file`, unless another file shares it. Then, so that the names
+  // of their headers differ, as must the include guards ispc derives from
+  // them, the whole path to `file` is used, such as `src_a_kernel`.
+  fn get_unique_stem(&self, file: &Path) -> String {
+    let stem = file.file_stem().unwrap();
+    if self.files.iter().filter(|f| f.file_stem() == Some(stem)).count() < 2 {
+      return stem.to_string_lossy().into_owned();
+    }
+    file.with_extension("").to_string_lossy()
+      .chars()
+      .map(|c| if c.is_alphanumeric() { c } else { '_' })
+      .collect()
   }
 
-  /// Enables or disables warnings.
-  ///
-  /// Default value: `true`
-  pub fn warn(&mut self, val: bool) -> &mut Self {
-    self.warnings = val;
-    self
+  // Compiles one source file to objects, or to C++ for a generic target.
+  fn compile_file(&self, file: &Path, dst: &Path, emit_hdr: bool) -> Result<Compiled, Error> {
+    let cfg = self.for_file(file);
+    cfg.check_targets()?;
+
+    let mut base = cfg.basic_tool()?;
+    for f in &self.before_compile.0 { f(file, &mut base); }
+    let llvm_base = base.clone();
+    for a in cfg.get_emit_args()? { base.arg(&a); }
+
+    let out = get_out_path(dst, file);
+    let obj: PathBuf = out.with_extension(if self.is_msvc()? { "obj" } else { "o" });
+    let hdr: PathBuf = out.with_file_name(format!("{}.h", self.get_unique_stem(file)));
+    let dep: PathBuf = out.with_extension("dep");
+    let emit_hdr = if emit_hdr { Some(&*hdr) } else { None };
+
+    if cfg.get_generic()? {
+      let cpp: PathBuf = out.with_extension("cpp");
+      cfg.compile_object(file, emit_hdr, &dep, &cpp, base)?;
+      self.after_compile_file(file, vec![], Some(&cpp), emit_hdr);
+      return Ok(Compiled {
+        objects: vec![],
+        cpp: Some(cpp),
+        header: hdr,
+        deps: self.get_deps(file, &[ dep ])?,
+        knc: cfg.get_targets()?.contains(&Target::Knc),
+        math_lib: cfg.math_lib,
+      });
+    }
+
+    let deps = if cfg.needs_per_target()? {
+      cfg.compile_per_target(file, emit_hdr, &dep, &obj, &base, &llvm_base)?
+    } else {
+      cfg.compile_object(file, emit_hdr, &dep, &obj, base)?;
+      cfg.compile_llvm(file, &dep, &obj, llvm_base.clone())?;
+      cfg.compile_asm(file, &dep, &obj, llvm_base)?;
+      vec![ dep ]
+    };
+    let mut objects = vec![ obj.clone() ];
+    objects.extend(cfg.get_target_outputs(&obj)?);
+    if let Some(o) = objects.iter().find(|o| !o.exists()) {
+      return Err(Error::MissingObject { path: o.clone() });
+    }
+    remove_stale(&obj, &objects)?;
+    if cfg.get_debug()? {
+      for o in &objects {
+        for &(ref from, ref to) in self.path_remaps.iter().rev() {
+          remap_object(o, from, to)?;
+        }
+      }
+    }
+    if self.hide_symbols && !self.is_msvc()? {
+      cfg.hide_symbols(&hdr, &objects)?;
+    }
+    self.after_compile_file(file, objects.clone(), None, emit_hdr);
+
+    Ok(Compiled {
+      objects: objects,
+      cpp: None,
+      header: hdr,
+      deps: self.get_deps(file, &deps)?,
+      knc: false,
+      math_lib: cfg.math_lib,
+    })
   }
 
-  /// Enables or disables warnings about suboptimal code performance.
-  ///
-  /// These will not turn into errors *even with `.werror(true)`*, so cargo will
-  /// silently ignore them anyhow. This is really suboptimal. Sorry.
-  ///
-  /// Bikeshed about it [here](https://github.com/rust-lang/cargo/issues/1106).
-  ///
-  /// Default value: `true`
-  pub fn warn_perf(&mut self, val: bool) -> &mut Self {
-    self.wperf = val;
-    self
+  fn after_compile_file(&self, file: &Path, objects: Vec<PathBuf>, cpp: Option<&Path>,
+                        header: Option<&Path>) {
+    if self.after_compile.0.is_empty() { return; }
+    let compiled = CompiledObject {
+      objects: objects,
+      cpp: cpp.map(|p| p.to_path_buf()),
+      header: header.map(|p| p.to_path_buf()),
+    };
+    for f in &self.after_compile.0 { f(file, &compiled); }
   }
 
-  fn get_opt_level(&self) -> u32 {
-    match self.opt_level {
-      Some(ol) => cmp::min(ol, 3),
-      None     => self.getenv_unwrap("OPT_LEVEL").parse().unwrap()
+  fn compile_spirv(&self, outbase: &str, dst: &Path) -> Result<Vec<PathBuf>, Error> {
+    let compiled: Result<Vec<(PathBuf, Vec<PathBuf>)>, Error> = parallel_map(self.get_jobs(), &self.files, |file| {
+      let cfg = self.for_file(file);
+      cfg.check_targets()?;
+
+      let mut base = cfg.basic_tool()?;
+      for f in &self.before_compile.0 { f(file, &mut base); }
+      for a in cfg.get_emit_args()? { base.arg(&a); }
+
+      let out = get_out_path(dst, file);
+      let spv: PathBuf = out.with_extension("spv");
+      let dep: PathBuf = out.with_extension("dep");
+      cfg.compile_object(file, None, &dep, &spv, base)?;
+      self.after_compile_file(file, vec![ spv.clone() ], None, None);
+
+      let var: String =
+        format!("{}_{}_SPV", outbase, self.get_unique_stem(file))
+          .chars()
+          .map(|c| if c.is_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+          .collect();
+      println!("cargo:rustc-env={}={}", var, spv.display());
+
+      Ok((spv, self.get_deps(file, &[ dep ])?))
+    }).into_iter().collect();
+
+    let mut modules = vec![];
+    let mut deps = vec![];
+    for (spv, d) in compiled? {
+      modules.push(spv);
+      deps.extend(d);
     }
+    print_deps(&deps);
+    Ok(modules)
   }
 
-  fn get_debug(&self) -> bool {
-    self.debug.unwrap_or_else(|| self.getenv_unwrap("PROFILE") == "debug")
+  fn print_math_links(&self, libs: &[Math]) -> Result<(), Error> {
+    let windows = self.get_target_windows()?;
+
+    // The C runtime carries the math functions on Windows.
+    if libs.contains(&Math::System) && !windows {
+      println!("cargo:rustc-link-lib=m");
+    }
+
+    if libs.contains(&Math::Svml) {
+      if let Some(ref p) = self.svml_path {
+        println!("cargo:rustc-link-search=native={}", p.display());
+      }
+      println!("cargo:rustc-link-lib={}", if windows { "svml_dispmt" } else { "svml" });
+    }
+    Ok(())
   }
 
-  fn get_targets(&self) -> Vec<Target> {
-    match self.targets.clone() {
-      None    => vec![ Target::Sse2, Target::Sse4, Target::Avx1, Target::Avx1_1, Target::Avx2 ],
-      Some(t) => t,
+  // `file` itself, and everything the dependency files written by ispc while
+  // compiling it list.
+  fn get_deps(&self, file: &Path, dep_files: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+    let mut deps = vec![ file.to_path_buf() ];
+    for dep in dep_files {
+      for d in io::BufReader::new(fs::File::open(dep)?).lines() {
+        let d = self.get_dep_path(&d?)?;
+        if !deps.contains(&d) { deps.push(d); }
+      }
     }
+    Ok(deps)
   }
 
-  fn get_x64(&self) -> bool {
-    let t = self.getenv_unwrap("TARGET");
-    if      t.contains("x86_64")                     { true }
-    else if t.contains("i686") || t.contains("i586") { false }
-    else { fail(&format!("ispc can only target x86 or x86_64. Your current target is {}", t)) }
+  // Dependencies are listed relative to the directory ispc ran in.
+  fn get_dep_path(&self, d: &str) -> Result<PathBuf, Error> {
+    Ok(match self.get_current_dir()? {
+      Some(ref dir) => dir.join(d),
+      None          => PathBuf::from(d),
+    })
+  }
+}
+
+// What compiling one source file produced.
+struct Compiled {
+  objects: Vec<PathBuf>,
+  // The C++ emitted for a generic target, which still needs compiling.
+  cpp: Option<PathBuf>,
+  header: PathBuf,
+  // What to rerun the build script on changes to.
+  deps: Vec<PathBuf>,
+  knc: bool,
+  math_lib: Math,
+}
+
+// The name of the library `output`, less any `lib` prefix and `.a` extension.
+fn get_lib_name(output: &str) -> Result<&str, Error> {
+  let name = if output.starts_with("lib") && output.ends_with(".a") && output.len() > 5 {
+    &output[3..output.len() - 2]
+  } else {
+    output
+  };
+  if name.is_empty() || name.contains(&['/', '\\', '.'][..]) {
+    return Err(Error::InvalidConfig(format!("`{}` is not a library name, such as `mandelbrot`",
+                                            output)));
   }
+  Ok(name)
+}
+
+// The C or C++ compiler `c` would run.
+fn cc_tool(c: &cc::Build) -> Tool {
+  let cmd = c.get_compiler().to_command();
+  let mut t = Tool::new(PathBuf::from(cmd.get_program()));
+  for a in cmd.get_args() { t.arg(a); }
+  t.envs = cmd.get_envs()
+              .filter_map(|(k, v)| v.map(|v| (k.to_owned(), v.to_owned())))
+              .collect();
+  t
+}
+
+// A function declared in Rust bindings, with its parameters and the names of
+// those, and the rest of its signature, such as ` -> f32`.
+struct RustFn {
+  name: String,
+  params: String,
+  args: Vec<String>,
+  ret: String,
+}
 
-  fn get_arch(&self) -> Arch {
-    if let Some(x) = self.architecture { x }
-    else if self.get_x64() { Arch::X86_64 }
-    else                   { Arch::X86 }
+// The `pub fn`s the Rust `bindings` generated for ispc's headers declare.
+fn get_rust_fns(bindings: &str) -> Vec<RustFn> {
+  let mut fns = vec![];
+  for decl in bindings.split("pub fn ").skip(1) {
+    // Skipping the functions of `impl`s, which have bodies.
+    let decl = match decl.find(';') {
+      Some(end) if !decl[..end].contains('{') =>
+        decl[..end].split_whitespace().collect::<Vec<_>>().join(" "),
+      _ => continue,
+    };
+    let open = match decl.find('(') { Some(i) => i, None => continue };
+    // The parameters may themselves be function pointers, with their own.
+    let mut depth = 0;
+    let mut close = None;
+    let mut starts = vec![open + 1];
+    for (i, c) in decl.char_indices().skip(open) {
+      match c {
+        '(' | '[' | '<' => depth += 1,
+        ')' | ']' => { depth -= 1; if depth == 0 { close = Some(i); break; } },
+        '>' if !decl[..i].ends_with('-') => depth -= 1,
+        ',' if depth == 1 => starts.push(i + 1),
+        _ => {},
+      }
+    }
+    let close = match close { Some(i) => i, None => continue };
+    let params = decl[open + 1..close].trim().to_string();
+    let args = starts.iter().zip(starts.iter().skip(1).map(|&s| s - 1).chain(Some(close)))
+      .filter_map(|(&s, e)| decl[s..e].split(':').next())
+      .map(|a| a.trim().to_string())
+      .filter(|a| !a.is_empty())
+      .collect();
+    let ret = decl[close + 1..].trim();
+    fns.push(RustFn {
+      name: decl[..open].trim().to_string(),
+      params: params,
+      args: args,
+      ret: if ret.is_empty() { String::new() } else { format!(" {}", ret) },
+    });
   }
+  fns
+}
+
+// The functions a header written by ispc declares, which are those its source
+// `export`s.
+fn get_exports(header: &str) -> Vec<String> {
+  header.lines()
+    .map(|l| l.trim())
+    .filter(|l| l.starts_with("extern ") && l.ends_with(';'))
+    .filter_map(|l| l.split('(').next())
+    .filter_map(|l| l.rsplit(|c: char| c.is_whitespace() || c == '*').next())
+    .filter(|n| !n.is_empty())
+    .map(|n| n.to_string())
+    .collect()
+}
+
+/// Compile a library from the given set of input `.ispc` files.
+///
+/// This will simply compile all files into object files and then assemble them
+/// into the output. This will read the standard environment variables to detect
+/// cross compilation and such.
+///
+/// This function will also print all metadata on standard output for Cargo.
+pub fn compile_library(output: &str, files: &[&str]) {
+  let mut c = Config::new();
+  for f in files { c.file(f); }
+  c.compile(output);
+}
 
-  fn get_pic(&self) -> bool {
-    if let Some(x) = self.pic { x }
-    else { self.get_x64() }
+/// Combine several static libraries, such as those built by `compile`, and
+/// object files into the one library `output`.
+///
+/// This function will also print all metadata on standard output for Cargo.
+pub fn merge_libraries<P: AsRef<Path>>(output: &str, inputs: &[P]) {
+  if let Err(e) = Config::new().try_merge_libraries(output, inputs) {
+    fail(&e.to_string());
   }
+}
+
+// The names and contents of the members of the archive at `path`, without its
+// symbol tables, or `None` if it isn't an archive. The members of a thin
+// archive are read from where it refers to.
+fn read_archive(path: &Path) -> io::Result<Option<Vec<Member>>> {
+  let data = fs::read(path)?;
+  let thin = data.starts_with(b"!<thin>\n");
+  if !thin && !data.starts_with(b"!<arch>\n") { return Ok(None); }
+
+  let invalid = || io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("`{}` is not a valid archive", path.display()));
+  let mut members = vec![];
+  let mut long_names: &[u8] = &[];
+  let mut pos = 8;
+  while pos + 60 <= data.len() {
+    let header = &data[pos..pos + 60];
+    let size: usize = String::from_utf8_lossy(&header[48..58]).trim().parse().map_err(|_| invalid())?;
+    let name = String::from_utf8_lossy(&header[..16]).trim_end().to_string();
+    pos += 60;
+
+    // Only the symbol tables and long names are kept in a thin archive.
+    let stored = !thin || name == "/" || name == "//" || name == "/SYM64/";
+    let mut body: &[u8] = if stored {
+      data.get(pos..pos + size).ok_or_else(invalid)?
+    } else {
+      &[]
+    };
+    if stored { pos += size + size % 2; }
 
-  fn get_defs(&self) -> Vec<(String, Option<String>)> {
-    self.definitions.clone()
+    let name = if name == "//" {
+      long_names = body;
+      continue;
+    } else if name == "/" || name == "/SYM64/" {
+      continue;
+    } else if let Some(len) = name.strip_prefix("#1/") {
+      // BSD, with the name at the start of the body.
+      let len: usize = len.parse().map_err(|_| invalid())?;
+      let n = body.get(..len).ok_or_else(invalid)?;
+      let n = String::from_utf8_lossy(n).trim_end_matches('\0').to_string();
+      body = &body[len..];
+      n
+    } else if let Some(at) = name.strip_prefix('/') {
+      // GNU or MSVC, with the name in the table of long names.
+      let at: usize = at.parse().map_err(|_| invalid())?;
+      let rest = long_names.get(at..).ok_or_else(invalid)?;
+      let end = rest.iter().position(|&b| b == b'\n' || b == 0).unwrap_or(rest.len());
+      String::from_utf8_lossy(&rest[..end]).trim_end_matches('/').to_string()
+    } else {
+      name.trim_end_matches('/').to_string()
+    };
+    if name.starts_with("__.SYMDEF") { continue; }
+
+    let contents = if thin {
+      fs::read(path.parent().unwrap_or(Path::new(".")).join(&name))?
+    } else {
+      body.to_vec()
+    };
+    let name = Path::new(&name).file_name().map_or(name.clone(), |n| n.to_string_lossy().into_owned());
+    members.push((name, contents));
   }
+  Ok(Some(members))
+}
 
-  fn get_out_dir(&self) -> PathBuf {
-    std::env::var_os("OUT_DIR").map(PathBuf::from).unwrap()
+/// Links the library built by the build script of a dependency with the
+/// `links` key `links`, rather than compiling it again, and returns where it
+/// and its header are. See `Config::compile` for what the dependency passes
+/// on. Panics if it can't be found; see `try_import_prebuilt` to handle errors
+/// instead.
+pub fn import_prebuilt(links: &str) -> Prebuilt {
+  match try_import_prebuilt(links) {
+    Ok(p)  => p,
+    Err(e) => fail(&e.to_string()),
   }
+}
 
-  fn getenv(&self, v: &str) -> Option<String> {
-    let r = std::env::var(v).ok();
-    println!("{} = {:?}", v, r);
-    r
+/// Like `import_prebuilt`, but returns any error rather than panicking.
+pub fn try_import_prebuilt(links: &str) -> Result<Prebuilt, Error> {
+  let c = Config::new();
+  let var = |key: &str| format!("DEP_{}_{}", links.to_uppercase().replace('-', "_"), key);
+
+  let lib = PathBuf::from(c.getenv_unwrap(&var("LIB"))?);
+  let name = lib.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+  let name = if name.ends_with(".lib") { &name[..name.len() - 4] } else { &*name };
+  let name = get_lib_name(name)?;
+  let dir = lib.parent().unwrap_or(Path::new("."));
+  println!("cargo:rustc-link-lib=static={}", name);
+  println!("cargo:rustc-link-search=native={}", dir.display());
+
+  let ispc_includes = match c.getenv(&var("ISPC_INCLUDE")) {
+    Some(dirs) => std::env::split_paths(&dirs).collect(),
+    None       => vec![],
+  };
+  Ok(Prebuilt {
+    lib: lib,
+    header: c.getenv(&var("HEADER")).map(PathBuf::from),
+    include: c.getenv(&var("INCLUDE")).map(PathBuf::from),
+    ispc_includes: ispc_includes,
+  })
+}
+
+// Writes the commands of `timings` to the `compile_commands.json` at `path`,
+// along with those of any other library written there before.
+fn write_compile_commands(path: &Path, libname: &str, timings: &[Timing]) -> io::Result<()> {
+  static FILES: Mutex<Vec<(PathBuf, String, Vec<String>)>> = Mutex::new(Vec::new());
+
+  let mut entries = vec![];
+  for t in timings {
+    let args: Vec<String> = t.command.iter().map(|a| json_str(a)).collect();
+    entries.push(format!(
+      "  {{\n    \"directory\": {},\n    \"arguments\": [{}],\n    \"file\": {},\n    \
+       \"output\": {}\n  }}",
+      json_str(&t.directory.to_string_lossy()), args.join(", "),
+      json_str(&t.file.to_string_lossy()), json_str(&t.output.to_string_lossy())));
   }
 
-  fn getenv_unwrap(&self, v: &str) -> String {
-    match self.getenv(v) {
-      Some(s) => s,
-      None => fail(&format!("environment variable `{}` not defined", v)),
+  let mut files = FILES.lock().unwrap();
+  files.retain(|&(ref p, ref l, _)| !(p == path && l == libname));
+  files.push((path.to_path_buf(), libname.to_string(), entries));
+  let all: Vec<&str> =
+    files.iter().filter(|&&(ref p, _, _)| p == path).flat_map(|f| f.2.iter().map(|e| &**e)).collect();
+  fs::create_dir_all(path.parent().unwrap())?;
+  fs::write(path, format!("[\n{}\n]\n", all.join(",\n")))
+}
+
+// `s` as a JSON string.
+fn json_str(s: &str) -> String {
+  let mut q = String::from("\"");
+  for c in s.chars() {
+    match c {
+      '"'  => q.push_str("\\\""),
+      '\\' => q.push_str("\\\\"),
+      c if (c as u32) < 0x20 => q.push_str(&format!("\\u{:04x}", c as u32)),
+      c    => q.push(c),
     }
   }
+  q.push('"');
+  q
+}
 
-  fn get_base_compiler(&self) -> Tool {
-    Tool::new(PathBuf::from(self.getenv("ISPC").unwrap_or("ispc".into())))
+// The `timings` in the JSON format read by `chrome://tracing`, each run of
+// ispc on a row of its own, unless they didn't overlap.
+fn trace_json(timings: &[Timing]) -> String {
+  let mut rows: Vec<Duration> = vec![];
+  let mut events = vec![];
+  for t in timings {
+    let row = match rows.iter().position(|&end| end <= t.start) {
+      Some(row) => row,
+      None      => { rows.push(Duration::default()); rows.len() - 1 },
+    };
+    rows[row] = t.start + t.duration;
+    events.push(format!(
+      "{{\"name\":{},\"cat\":\"ispc\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":{},\
+       \"args\":{{\"output\":{},\"targets\":{},\"compiled\":{}}}}}",
+      json_str(&t.file.to_string_lossy()), t.start.as_micros(), t.duration.as_micros(), row + 1,
+      json_str(&t.output.to_string_lossy()), json_str(&t.targets), t.compiled));
   }
+  format!("{{\"traceEvents\":[\n{}\n]}}\n", events.join(",\n"))
+}
 
-  fn basic_tool(&self) -> Tool {
-    let mut t = self.get_base_compiler();
+// Flags and target names which older releases of ispc spelled differently,
+// from before its GPU support was renamed from "genx" to "xe", and from before
+// it had a target of its own for Knights Corner.
+const OLD_SPELLINGS: &[(&str, &str)] = &[
+  ("--arch=xe64", "--arch=genx64"),
+  ("gen9-x8",     "genx-x8"),
+  ("gen9-x16",    "genx-x16"),
+  ("knc",         "generic-16"),
+];
 
-    match self.addressing {
-      Some(Addr::A32) => { t.arg("--addressing=32"); },
-      Some(Addr::A64) => { t.arg("--addressing=64"); },
-      None => {},
+fn old_spelling(s: &str) -> Option<&'static str> {
+  OLD_SPELLINGS.iter().find(|&&(new, _)| new == s).map(|&(_, old)| old)
+}
+
+fn is_spelling_of(s: &str, name: &str) -> bool {
+  s == name || old_spelling(name) == Some(s)
+}
+
+// Prints a cargo warning, unless the same one has been printed already.
+fn warn_once(msg: &str) {
+  static WARNED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+  let mut warned = WARNED.lock().unwrap();
+  if !warned.iter().any(|w| w == msg) {
+    println!("cargo:warning={}", msg);
+    warned.push(msg.into());
+  }
+}
+
+// Reruns the build script when `v` changes, unless it's one which cargo sets,
+// and so only changes along with things which rerun it anyway.
+fn watch_env(v: &str) {
+  const SET_BY_CARGO: &[&str] =
+    &[ "TARGET", "HOST", "OUT_DIR", "PROFILE", "OPT_LEVEL", "DEBUG", "NUM_JOBS" ];
+  if v.starts_with("CARGO_") || SET_BY_CARGO.contains(&v) { return; }
+
+  static WATCHED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+  let mut watched = WATCHED.lock().unwrap();
+  if !watched.iter().any(|w| w == v) {
+    println!("cargo:rerun-if-env-changed={}", v);
+    watched.push(v.into());
+  }
+}
+
+/// Finds out which version of the ispc named by the `ISPC` environment
+/// variable, or on the `PATH`, is installed.
+pub fn ispc_version() -> Version {
+  Config::new().ispc_version()
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn detect_host() -> Option<(Cpu, Target)> {
+  #[cfg(target_arch = "x86")]
+  use std::arch::x86::__cpuid;
+  #[cfg(target_arch = "x86_64")]
+  use std::arch::x86_64::__cpuid;
+
+  #[allow(unused_unsafe)]
+  let vendor = unsafe { __cpuid(0) };
+  let amd = (vendor.ebx, vendor.edx, vendor.ecx) == (0x68747541, 0x69746e65, 0x444d4163);
+
+  Some(if is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw") &&
+          is_x86_feature_detected!("avx512dq") && is_x86_feature_detected!("avx512vl") {
+    (if amd { Cpu::Znver3 } else { Cpu::Skx }, Target::Avx512skx_i32x16)
+  } else if is_x86_feature_detected!("avx2") {
+    (if amd { Cpu::Znver1 } else { Cpu::Core_avx2 }, Target::Avx2)
+  } else if is_x86_feature_detected!("avx") {
+    if is_x86_feature_detected!("f16c") && is_x86_feature_detected!("rdrand") {
+      (if amd { Cpu::Btver2 } else { Cpu::Core_avx_i }, Target::Avx1_1)
+    } else {
+      (Cpu::Corei7_avx, Target::Avx1)
     }
+  } else if is_x86_feature_detected!("sse4.2") {
+    (Cpu::Corei7, Target::Sse4)
+  } else if is_x86_feature_detected!("sse4.1") {
+    (Cpu::Penryn, Target::Sse4)
+  } else if is_x86_feature_detected!("ssse3") {
+    (Cpu::Core2, Target::Sse2)
+  } else {
+    (Cpu::Generic, Target::Sse2)
+  })
+}
 
-    match self.get_arch() {
-      Arch::X86 => t.arg("--arch=x86"),
-      Arch::X86_64 => t.arg("--arch=x86_64"),
-    };
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn detect_host() -> Option<(Cpu, Target)> {
+  None
+}
+
+fn fail(s: &str) -> ! {
+  println!("\n\n{}\n\n", s);
+  panic!()
+}
 
-    t.arg("--colored-output");
+// Splits a requirement given to `Config::require_version` into its comparison
+// and version.
+fn parse_requirement(req: &str) -> Option<(&'static str, Version)> {
+  let op_len = req.find(|c: char| c.is_ascii_digit()).unwrap_or(req.len());
+  let (op, v) = req.split_at(op_len);
+  let op = match op.trim() {
+    "" | ">=" => ">=",
+    ">"       => ">",
+    "<="      => "<=",
+    "<"       => "<",
+    "=" | "==" => "=",
+    _ => return None,
+  };
+  Version::parse(v).map(|v| (op, v))
+}
 
-    match self.cpu.clone() {
-      None => {},
-      Some(cpus) => {
-        let mut cpu_s = String::new();
+// On Windows, where ispc and the archiver can be confused by a mix of `/` and
+// `\`, uses `\` throughout. A path too long for `MAX_PATH`, as deep `OUT_DIR`s
+// often are, is made absolute against `base` and given the `\\?\` prefix. That
+// lifts the limit, but also stops Windows resolving `.` and `..`, which is
+// done here instead. Elsewhere, `p` is returned as it is.
+fn native_path(p: &Path, base: &Path) -> PathBuf {
+  const MAX_PATH: usize = 260;
 
-        for (i, c) in cpus.into_iter().enumerate() {
-          cpu_s.push_str(if i == 0 { "--cpu=" } else { "," });
-          cpu_s.push_str(c.to_str());
-        }
+  if !cfg!(windows) { return p.to_path_buf(); }
+  let s = match p.to_str() {
+    Some(s) => s.replace('/', "\\"),
+    None    => return p.to_path_buf(),
+  };
+  if s.starts_with(r"\\?\") { return PathBuf::from(s); }
 
-        t.arg(&*cpu_s);
-      }
+  let abs = base.join(&s);
+  if s.len() < MAX_PATH && abs.as_os_str().len() < MAX_PATH { return PathBuf::from(s); }
+
+  let mut parts: Vec<String> = vec![];
+  let mut prefix = String::new();
+  for c in abs.components() {
+    match c {
+      Component::Prefix(p) => prefix = p.as_os_str().to_string_lossy().into_owned(),
+      Component::RootDir   => {},
+      Component::CurDir    => {},
+      Component::ParentDir => { parts.pop(); },
+      Component::Normal(n) => parts.push(n.to_string_lossy().into_owned()),
     }
+  }
+  let prefix = match prefix.strip_prefix(r"\\") {
+    Some(unc) => format!(r"\\?\UNC\{}", unc),
+    None      => format!(r"\\?\{}", prefix),
+  };
+  PathBuf::from(format!(r"{}\{}", prefix, parts.join("\\")))
+}
 
-    for (k, ov) in self.get_defs() {
-      match ov {
-        None    => { t.arg(&*format!("-D{}", k)); },
-        Some(v) => { t.arg(&*format!("-D{}={}", k, v)); },
-      }
+// Rewrites the paths in `obj` which begin with `from` to begin with `to`. They
+// are referred to by offset, so rather than move anything, what's left of
+// `from` is filled with separators, which don't change the path. Only whole
+// strings are paths, so `from` must follow a NUL, or begin the file.
+fn remap_object(obj: &Path, from: &Path, to: &Path) -> io::Result<()> {
+  let from = from.to_string_lossy();
+  let from = from.trim_end_matches(&['/', '\\'][..]).as_bytes();
+  let to = to.to_string_lossy();
+  let to = if to.is_empty() { "." } else { &*to };
+  let is_sep = |b: u8| b == b'/' || b == b'\\';
+  if from.is_empty() || to.len() > from.len() {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                              format!("can't remap `{}` to `{}`, which is longer",
+                                      String::from_utf8_lossy(from), to)));
+  }
+
+  let mut data = fs::read(obj)?;
+  let mut changed = false;
+  let mut i = 0;
+  while let Some(at) = data[i..].windows(from.len()).position(|w| w == from) {
+    let at = i + at;
+    let end = at + from.len();
+    let prev = if at == 0 { 0 } else { data[at - 1] };
+    let next = data.get(end).cloned().unwrap_or(0);
+    if prev != 0 || !(next == 0 || is_sep(next)) {
+      i = at + 1;
+      continue;
     }
+    let sep = if is_sep(next) { next } else { b'/' };
+    data[at..at + to.len()].copy_from_slice(to.as_bytes());
+    for b in &mut data[at + to.len()..end] { *b = sep; }
+    changed = true;
+    i = end;
+  }
+  if changed { fs::write(obj, data)?; }
+  Ok(())
+}
 
-    t.arg("--emit-obj");
+// The size of the code in the object `obj`, or `None` if it isn't ELF, COFF or
+// Mach-O, with the sections of each read straight from the headers.
+fn text_size(obj: &Path) -> io::Result<Option<u64>> {
+  let data = fs::read(obj)?;
+  let read = |at: usize, n: usize, be: bool| -> Option<u64> {
+    let bytes = data.get(at..at + n)?;
+    let fold = |v: u64, &b: &u8| v << 8 | b as u64;
+    Some(if be { bytes.iter().fold(0, fold) } else { bytes.iter().rev().fold(0, fold) })
+  };
+  let mut size = 0;
 
-    if let Some(align) = self.force_alignment {
-      t.arg(&*format!("--force-alignment={}", align));
+  if data.starts_with(b"\x7fELF") {
+    let wide = data.get(4) == Some(&2);
+    let be = data.get(5) == Some(&2);
+    let (shoff, shentsize, shnum) = if wide { (0x28, 0x3a, 0x3c) } else { (0x20, 0x2e, 0x30) };
+    let shoff = match read(shoff, if wide { 8 } else { 4 }, be) {
+      Some(o) => o as usize,
+      None    => return Ok(None),
+    };
+    let shentsize = read(shentsize, 2, be).unwrap_or(0) as usize;
+    for i in 0..read(shnum, 2, be).unwrap_or(0) as usize {
+      let sh = shoff + i * shentsize;
+      let (flags, len) = if wide { (read(sh + 8, 8, be), read(sh + 0x20, 8, be)) }
+                         else { (read(sh + 8, 4, be), read(sh + 0x14, 4, be)) };
+      // SHF_EXECINSTR
+      if flags.is_some_and(|f| f & 0x4 != 0) { size += len.unwrap_or(0); }
     }
+    return Ok(Some(size));
+  }
 
-    if self.get_debug() {
-      t.arg("-g");
+  if data.starts_with(&[0xcf, 0xfa, 0xed, 0xfe]) || data.starts_with(&[0xce, 0xfa, 0xed, 0xfe]) {
+    let wide = data[0] == 0xcf;
+    let mut cmd = if wide { 32 } else { 28 };
+    for _ in 0..read(16, 4, false).unwrap_or(0) {
+      let (kind, cmdsize) = match (read(cmd, 4, false), read(cmd + 4, 4, false)) {
+        (Some(k), Some(s)) if s > 0 => (k, s as usize),
+        _                           => break,
+      };
+      // LC_SEGMENT_64 and LC_SEGMENT, whose sections follow.
+      if (wide && kind == 0x19) || (!wide && kind == 0x1) {
+        let (nsects, first, sectsize) = if wide { (64, 72, 80) } else { (48, 56, 68) };
+        for i in 0..read(cmd + nsects, 4, false).unwrap_or(0) as usize {
+          let sect = cmd + first + i * sectsize;
+          let (len, flags) = if wide { (read(sect + 40, 8, false), read(sect + 64, 4, false)) }
+                             else { (read(sect + 36, 4, false), read(sect + 56, 4, false)) };
+          // S_ATTR_PURE_INSTRUCTIONS or S_ATTR_SOME_INSTRUCTIONS
+          if flags.is_some_and(|f| f & 0x80000400 != 0) { size += len.unwrap_or(0); }
+        }
+      }
+      cmd += cmdsize;
     }
+    return Ok(Some(size));
+  }
 
-    t.arg(&*format!("--math-lib={}", self.math_lib.to_str()));
+  // COFF, as MSVC writes, which has no magic but the machine.
+  match read(0, 2, false) {
+    Some(0x14c) | Some(0x8664) | Some(0xaa64) | Some(0x1c4) => {},
+    _ => return Ok(None),
+  }
+  let sections = 20 + read(16, 2, false).unwrap_or(0) as usize;
+  for i in 0..read(2, 2, false).unwrap_or(0) as usize {
+    let sect = sections + i * 40;
+    // IMAGE_SCN_CNT_CODE
+    if read(sect + 36, 4, false).is_some_and(|c| c & 0x20 != 0) {
+      size += read(sect + 16, 4, false).unwrap_or(0);
+    }
+  }
+  Ok(Some(size))
+}
 
-    t.arg(&*format!("-O{:?}", self.get_opt_level()));
+// `stem.ext`, for a `stem` in which `Path::with_extension` might mistake part
+// of a name such as `kernel.v2` for an extension.
+fn file_name(stem: &OsStr, ext: &str) -> OsString {
+  let mut name = stem.to_owned();
+  if !ext.is_empty() {
+    name.push(".");
+    name.push(ext);
+  }
+  name
+}
 
-    if !self.assertations { t.arg("--opt=disable-assertations"); }
-    if !self.fma { t.arg("--opt=disable-fma"); }
-    if !self.loop_unroll { t.arg("--opt=disable-loop-unroll"); }
-    if self.fast_masked_vload { t.arg("--opt=fast-masked-vload"); }
-    if self.fast_math { t.arg("--opt=fast-math"); }
-    if self.force_aligned_memory { t.arg("--arg=fast-aligned-memory"); }
+// Rust's keywords, in every edition, including those reserved for later.
+const RUST_KEYWORDS: &[&str] = &[
+  "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+  "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+  "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub",
+  "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+  "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
 
-    if self.get_pic() { t.arg("--pic"); }
+// `name` made into a Rust identifier.
+fn rust_ident(name: &str) -> String {
+  let mut id: String = name.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect();
+  if id.is_empty() || id.starts_with(|c: char| c.is_ascii_digit()) { id.insert(0, '_'); }
+  if id == "_" || RUST_KEYWORDS.contains(&&*id) { id.push('_'); }
+  id
+}
 
-    let mut target_s = String::new();
+// The stem ispc gives the code for a single target of a multi-target build.
+fn target_stem(stem: &OsStr, suffix: &str) -> OsString {
+  let mut name = stem.to_owned();
+  name.push("_");
+  name.push(suffix);
+  name
+}
 
-    for (i, t) in self.get_targets().into_iter().enumerate() {
-      target_s.push_str(if i == 0 { "--target=" } else { "," });
-      target_s.push_str(t.to_str());
+// Where under `dst` to put what's compiled from `file`, mirroring its path so
+// that files sharing a name don't collide. Nothing may lead out of `dst`, so
+// `..` becomes `__`, and an absolute path is kept under `__root`.
+fn get_out_path(dst: &Path, file: &Path) -> PathBuf {
+  let mut out = dst.to_path_buf();
+  let mut rooted = false;
+  for c in file.components() {
+    match c {
+      Component::Prefix(p) => {
+        let drive: String = p.as_os_str().to_string_lossy().chars().filter(|c| c.is_alphanumeric()).collect();
+        out.push("__root");
+        out.push(drive);
+        rooted = true;
+      },
+      Component::RootDir   => if !rooted { out.push("__root"); },
+      Component::CurDir    => {},
+      Component::ParentDir => out.push("__"),
+      Component::Normal(n) => out.push(n),
     }
+  }
+  out
+}
 
-    t.arg(&*target_s);
+// Deletes the objects written for `obj` by an earlier build which no longer
+// belong to it, such as those of targets since deselected, so that nothing
+// looking through the directory picks them up. What was written is recorded
+// next to `obj`.
+fn remove_stale(obj: &Path, objects: &[PathBuf]) -> io::Result<()> {
+  let mut list = obj.as_os_str().to_owned();
+  list.push(".objects");
+  let list = PathBuf::from(list);
 
-    if self.werror { t.arg("--werror"); }
-    if !self.warnings { t.arg("--woff"); }
-    if !self.wperf { t.arg("--wno-perf"); }
+  if let Ok(old) = fs::read_to_string(&list) {
+    for o in old.lines().map(PathBuf::from) {
+      if !objects.contains(&o) { let _ = fs::remove_file(&o); }
+    }
+  }
 
-    t
+  let mut new = String::new();
+  for o in objects {
+    new.push_str(&o.to_string_lossy());
+    new.push('\n');
   }
+  fs::write(&list, new)
+}
 
-  fn compile_object(&self, file: &Path, hdr: &Path, dep: &Path, dst: &Path, mut t: Tool) {
-    fs::create_dir_all(&dst.parent().unwrap()).unwrap();
+// Once cargo is told of any file to watch, it watches only those, so every
+// source is listed along with its `#include`s. Headers shared between sources
+// are listed once.
+fn print_deps(deps: &[PathBuf]) {
+  let mut printed: Vec<&PathBuf> = vec![];
+  for d in deps {
+    if printed.contains(&d) { continue; }
+    println!("cargo:rerun-if-changed={}", d.display());
+    printed.push(d);
+  }
+}
 
-    t.arg("-h")
-     .arg(&*hdr.to_string_lossy())
-     .arg("-MMM")
-     .arg(&*dep.to_string_lossy())
-     .arg(&*file.to_string_lossy())
-     .arg("-o")
-     .arg(&*dst.to_string_lossy());
+// Bundles those of `files` which exist into one cache entry, each named by its
+// path relative to `root`: the length of the name, the name, the length of the
+// contents and the contents, with lengths as little-endian `u64`s.
+fn pack_files(root: &Path, files: &[PathBuf]) -> io::Result<Vec<u8>> {
+  let mut out = vec![];
+  for f in files.iter().filter(|f| f.exists()) {
+    let rel = match f.strip_prefix(root) {
+      Ok(rel) => rel,
+      Err(_)  => continue,
+    };
+    let name: Vec<String> = rel.iter().map(|c| c.to_string_lossy().into_owned()).collect();
+    let name = name.join("/");
+    let contents = fs::read(f)?;
+    out.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(&(contents.len() as u64).to_le_bytes());
+    out.extend_from_slice(&contents);
+  }
+  Ok(out)
+}
 
-    run(&mut t.to_command());
+// Writes the files bundled by `pack_files` back out under `root`.
+fn unpack_files(root: &Path, mut entry: &[u8]) -> io::Result<()> {
+  fn take<'a>(entry: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated cache entry");
+    if entry.len() < 8 { return Err(invalid()); }
+    let mut len = [0; 8];
+    len.copy_from_slice(&entry[..8]);
+    let len = u64::from_le_bytes(len) as usize;
+    if entry.len() - 8 < len { return Err(invalid()); }
+    let (v, rest) = entry[8..].split_at(len);
+    *entry = rest;
+    Ok(v)
   }
 
-  /// Runs the compiler, generating the `output`.
-  ///
-  /// The name  `output` must begin with `lib` and end with `.a`.
-  pub fn compile(&self, output: &str) {
-    assert!(output.starts_with("lib"));
-    assert!(output.ends_with(".a"));
+  while !entry.is_empty() {
+    let name = String::from_utf8_lossy(take(&mut entry)?).into_owned();
+    let contents = take(&mut entry)?;
+    if name.split('/').any(|c| c == ".." || c.is_empty()) {
+      return Err(io::Error::new(io::ErrorKind::InvalidData, "bad name in cache entry"));
+    }
+    let path = root.join(&name);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, contents)?;
+  }
+  Ok(())
+}
 
-    let outbase = unsafe { output.slice_unchecked(3, output.len() - 2) };
+// FNV-1a, which unlike `DefaultHasher` is the same from one release of Rust
+// to the next.
+struct StableHasher(u64);
 
-    let dst = self.get_out_dir();
+impl StableHasher {
+  fn new() -> StableHasher {
+    StableHasher(0xcbf29ce484222325)
+  }
+}
 
-    let base = self.basic_tool();
+impl Hasher for StableHasher {
+  fn finish(&self) -> u64 {
+    self.0
+  }
 
-    let mut objects = Vec::new();
-    let mut headers = Vec::new();
+  fn write(&mut self, bytes: &[u8]) {
+    for &b in bytes {
+      self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+    }
+  }
+}
 
-    for file in self.files.iter() {
-      let lfile = file.file_stem().unwrap().to_string_lossy();
-      let obj: PathBuf = dst.join(file).with_extension("o");
-      let hdr: PathBuf = dst.join(file).with_extension("h");
-      let dep: PathBuf = dst.join(file).with_extension("dep");
-      self.compile_object(file, &hdr, &dep, &obj, base.clone());
-      let candidates : Vec<PathBuf> =
-        vec![ obj.clone(),
-              obj.clone().with_file_name(format!("{}_sse2",  lfile)).with_extension("o"),
-              obj.clone().with_file_name(format!("{}_sse4",  lfile)).with_extension("o"),
-              obj.clone().with_file_name(format!("{}_avx",   lfile)).with_extension("o"),
-              obj.clone().with_file_name(format!("{}_avx11", lfile)).with_extension("o"),
-              obj.clone().with_file_name(format!("{}_avx2",  lfile)).with_extension("o")
-        ];
-
-      for c in candidates {
-        if c.exists() {
-          objects.push(c);
-        }
-      }
+// Calls `f` on every item, on up to `jobs` threads, counting the calling one
+// and any already helping other calls. Returns the results in the order of
+// `items`.
+fn parallel_map<T: Sync, R: Send, F: Fn(&T) -> R + Sync>(jobs: usize, items: &[T], f: F) -> Vec<R> {
+  // The threads helping out, across all calls, since those for each file map
+  // over its targets in turn. The calling thread is always one of the `jobs`.
+  static HELPERS: AtomicUsize = AtomicUsize::new(0);
 
-      headers.push(hdr);
+  let want = cmp::min(jobs, items.len()).saturating_sub(1);
+  let mut helpers = 0;
+  let _ = HELPERS.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |busy| {
+    helpers = cmp::min(want, jobs.saturating_sub(1).saturating_sub(busy));
+    Some(busy + helpers)
+  });
+  if helpers == 0 { return items.iter().map(f).collect(); }
 
-      let deps = io::BufReader::new(fs::File::open(dep).unwrap());
+  let next = AtomicUsize::new(0);
+  let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+  let work = || loop {
+    let i = next.fetch_add(1, Ordering::SeqCst);
+    if i >= items.len() { break; }
+    let r = f(&items[i]);
+    results.lock().unwrap()[i] = Some(r);
+  };
+  thread::scope(|s| {
+    for _ in 0..helpers { s.spawn(work); }
+    work();
+  });
+  HELPERS.fetch_sub(helpers, Ordering::SeqCst);
+  results.into_inner().unwrap().into_iter().map(|r| r.unwrap()).collect()
+}
 
-      for d in deps.lines() { println!("cargo:rerun-if-changed={}", d.unwrap()); }
-    }
+// Passes the warnings in ispc's `diags` on to Cargo, which otherwise only shows
+// the output of failed builds. Each is only passed on once, though ispc repeats
+// them for every target, and for every file including the same header.
+fn print_warnings(diags: &str) {
+  static SEEN: Mutex<Vec<String>> = Mutex::new(Vec::new());
 
-    let mut c = gcc::Config::new();
-    for o in &objects { c.object(&*o); }
-    c.compile(output);
+  let mut seen = SEEN.lock().unwrap();
+  for l in diags.lines().map(strip_ansi) {
+    if !l.contains(": Warning: ") && !l.contains(": Performance Warning: ") { continue; }
+    if seen.contains(&l) { continue; }
+    println!("cargo:warning={}", l);
+    seen.push(l);
+  }
+}
 
-    let superheader =
-      dst.join(outbase)
-         .with_extension("h");
+// The `file:line:col: Kind: message` lines of ispc's diagnostics, skipping the
+// source snippets between them. The location is missing from some.
+fn parse_diagnostics(stderr: &str) -> Vec<Diagnostic> {
+  let kinds = [ Severity::PerformanceWarning, Severity::Warning, Severity::Error ];
 
-    {
-      let mut bindgen_f = fs::File::create(superheader.clone()).unwrap();
-      for h in headers {
-        write!(bindgen_f, "#include \"{}\"\n", h.display()).unwrap();
+  let mut ds = vec![];
+  for l in stderr.lines().map(strip_ansi) {
+    // The kind follows the location, at the first `: ` it can.
+    let mut start = 0;
+    let found = loop {
+      let rest = &l[start..];
+      if let Some(&k) = kinds.iter().find(|k| rest.starts_with(&format!("{}: ", k.to_str()))) {
+        break Some(k);
       }
-    }
+      match rest.find(": ") {
+        Some(i) => start += i + 2,
+        None    => break None,
+      }
+    };
+    let severity = match found { Some(k) => k, None => continue };
 
-    let bindgen_dst = dst.join(outbase).with_extension("rs");
+    let loc = &l[..start.saturating_sub(2)];
+    let message = l[start + severity.to_str().len() + 2..].to_string();
+    let mut parts = loc.rsplitn(3, ':');
+    let col = parts.next().and_then(|c| c.parse().ok());
+    let line = parts.next().and_then(|l| l.parse().ok());
+    let (file, line, col) = match (parts.next(), line, col) {
+      (Some(f), Some(l), Some(c)) => (Some(PathBuf::from(f)), Some(l), Some(c)),
+      _ if loc.is_empty()         => (None, None, None),
+      _                           => (Some(PathBuf::from(loc)), None, None),
+    };
+    ds.push(Diagnostic { file: file, line: line, col: col, severity: severity, message: message });
+  }
+  ds
+}
 
-    bindgen::builder()
-      .emit_builtins()
-      .forbid_unknown_types()
-      .header(superheader.to_str().unwrap())
-      .link_static(outbase)
-      .generate()
-      .unwrap()
-      .write_to_file(bindgen_dst)
-      .unwrap();
+// Removes the color escapes from a line of ispc's output.
+fn strip_ansi(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    if c == '\x1b' {
+      for c in chars.by_ref() { if c.is_ascii_alphabetic() { break; } }
+    } else {
+      out.push(c);
+    }
   }
+  out
 }
 
-/// Compile a library from the given set of input `.ispc` files.
-///
-/// This will simply compile all files into object files and then assemble them
-/// into the output. This will read the standard environment variables to detect
-/// cross compilation and such.
-///
-/// This function will also print all metadata on standard output for Cargo.
-pub fn compile_library(output: &str, files: &[&str]) {
-  let mut c = Config::new();
-  for f in files { c.file(f); }
-  c.compile(output)
+// What a probe of the compiler printed, and whether it succeeded.
+#[derive(Clone)]
+struct Probe {
+  status: ExitStatus,
+  stdout: String,
+  stderr: String,
 }
 
-fn fail(s: &str) -> ! {
-  println!("\n\n{}\n\n", s);
-  panic!()
+// Runs `t`, a probe of the compiler such as `--help`, only once per build
+// script, however many `Config`s ask. The error is that of starting it.
+fn probe(t: &Tool) -> Result<Probe, String> {
+  static PROBES: Mutex<Vec<(Tool, Result<Probe, String>)>> = Mutex::new(Vec::new());
+
+  // Held while probing, so that `Config`s built at once don't all probe.
+  let mut probes = PROBES.lock().unwrap();
+  if let Some((_, p)) = probes.iter().find(|p| p.0 == *t) { return p.clone(); }
+  let p = match t.to_command().output() {
+    Ok(o)  => Ok(Probe {
+      status: o.status,
+      stdout: String::from_utf8_lossy(&o.stdout).into_owned(),
+      stderr: String::from_utf8_lossy(&o.stderr).into_owned(),
+    }),
+    Err(e) => Err(e.to_string()),
+  };
+  probes.push((t.clone(), p.clone()));
+  p
 }
 
-fn run(cmd: &mut Command) {
-  println!("running: {:?}", cmd);
-  let output =
-    match cmd.output() {
-      Ok(output) => output,
-      Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
-        fail(&format!("failed to execute command: {}\nIs `ispc` \
-                       not installed?", e));
-      },
-      Err(e) => fail(&format!("failed to execute command: {}", e)),
+// Runs `cmd`, dropping the lines of its stderr `filter` rejects. Unless
+// silent, the output is printed line by line as it arrives; otherwise it is
+// only printed if the command fails. Returns the filtered stderr.
+fn run(t: &Tool, verbosity: Verbosity, filter: &mut (dyn FnMut(&str) -> bool + Send))
+       -> Result<String, Error> {
+  let (status, stdout, stderr) = capture(t, verbosity, None, filter)?;
+  // Only missing after timing out.
+  let status = status.unwrap();
+
+  if !status.success() {
+    // Printed all at once, so the output of files compiling in parallel doesn't
+    // interleave.
+    if verbosity < Verbosity::Normal {
+      let mut log = format!("running: {:?}\n", t.to_command());
+      if !stdout.is_empty() {
+        log.push_str(&format!("\n--- stdout ---\n{}\n--- end stdout ---\n\n", stdout));
+      }
+      if !stderr.is_empty() {
+        log.push_str(&format!("\n--- stderr ---\n{}\n--- end stderr ---\n\n", stderr));
+      }
+      print!("{}", log);
+    }
+    return Err(Error::CompilerExited { status: status, stderr: stderr });
+  }
+
+  Ok(stderr)
+}
+
+// Like `run`, but leaves what to do about failure to the caller, and kills the
+// command after `timeout`. Returns how the command exited, unless it was
+// killed, its stdout and its filtered stderr.
+fn capture(t: &Tool, verbosity: Verbosity, timeout: Option<Duration>,
+           filter: &mut (dyn FnMut(&str) -> bool + Send))
+           -> Result<(Option<ExitStatus>, String, String), Error> {
+  let mut cmd = t.to_command();
+  cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+  let live = verbosity >= Verbosity::Normal;
+  if live {
+    println!("running: {:?}", cmd);
+  }
+  let mut child =
+    match cmd.spawn() {
+      Ok(child) => child,
+      Err(e)    => return Err(Error::MissingCompiler { path: t.path.clone(), reason: e.to_string() }),
+    };
+
+  // Calls `f` with each line of `r`, including its newline, until the end.
+  fn each_line<R: Read>(r: R, f: &mut dyn FnMut(&str)) -> io::Result<()> {
+    let mut r = io::BufReader::new(r);
+    let mut buf = vec![];
+    while r.read_until(b'\n', &mut buf)? > 0 {
+      if !buf.ends_with(b"\n") { buf.push(b'\n'); }
+      f(&String::from_utf8_lossy(&buf));
+      buf.clear();
+    }
+    Ok(())
+  }
+
+  // Waits for `child` to exit, killing it after `timeout`.
+  fn wait(child: &mut Child, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
+    let deadline = match timeout {
+      Some(t) => Instant::now() + t,
+      None    => return child.wait().map(Some),
     };
-  let status = output.status;
-  println!("{:?}", status);
-  let stdout = String::from_utf8_lossy(&output.stdout);
+    loop {
+      if let Some(status) = child.try_wait()? { return Ok(Some(status)); }
+      if Instant::now() >= deadline {
+        child.kill()?;
+        child.wait()?;
+        return Ok(None);
+      }
+      thread::sleep(Duration::from_millis(10));
+    }
+  }
+
+  let child_out = child.stdout.take().unwrap();
+  let child_err = child.stderr.take().unwrap();
+  let (status, stdout, stderr) = thread::scope(|s| {
+    let stdout = s.spawn(|| {
+      let mut stdout = String::new();
+      each_line(child_out, &mut |l| {
+        if live { print!("{}", l); }
+        stdout.push_str(l);
+      }).map(|()| stdout)
+    });
+    let stderr = s.spawn(|| {
+      let mut stderr = String::new();
+      each_line(child_err, &mut |l| {
+        if !filter(l.trim_end_matches('\n')) { return; }
+        if live { print!("{}", l); }
+        stderr.push_str(l);
+      }).map(|()| stderr)
+    });
+    // Killing the command closes its end of the pipes, ending the reads.
+    let status = wait(&mut child, timeout);
+    (status, stdout.join().unwrap(), stderr.join().unwrap())
+  });
+  let (status, stdout, stderr) = (status?, stdout?, stderr?);
 
-  if !stdout.is_empty() {
-    println!("\n--- stdout ---");
-    println!("{}", stdout);
-    println!("--- end stdout ---\n");
+  if verbosity == Verbosity::Verbose {
+    match status {
+      Some(ref s) => println!("{:?}", s),
+      None        => println!("killed after {:?}", timeout.unwrap()),
+    }
   }
 
-  let stderr = String::from_utf8_lossy(&output.stderr);
+  Ok((status, stdout, stderr))
+}
 
-  if !stderr.is_empty() {
-    println!("\n--- stderr ---");
-    println!("{}", stderr);
-    println!("--- end stderr ---\n");
+// Prints each of ispc's diagnostics once, with the line it is about and a caret
+// under the column, for failed builds. Multi-target builds repeat them for
+// every target, so which of `targets` each came from is unknown. Files are
+// relative to `cwd`.
+fn print_snippets(stderr: &str, targets: &str, cwd: &Path) {
+  let mut ds: Vec<Diagnostic> = vec![];
+  for d in parse_diagnostics(stderr) {
+    if !ds.contains(&d) { ds.push(d); }
+  }
+  if ds.is_empty() {
+    print!("\n--- stderr ---\n{}\n--- end stderr ---\n\n", stderr);
+    return;
   }
 
-  if !status.success() {
-    fail(&format!("command did not execute successfully, got: {}", status));
+  let mut log = String::new();
+  for d in &ds {
+    log.push_str(&format!("\n{}: {}\n", d.severity.to_str().to_lowercase(), d.message));
+    let file = match d.file {
+      Some(ref f) => f,
+      None        => continue,
+    };
+    let (line, col) = match (d.line, d.col) {
+      (Some(l), Some(c)) => (l, c),
+      _                  => { log.push_str(&format!("  --> {}\n", file.display())); continue },
+    };
+    log.push_str(&format!("  --> {}:{}:{}\n", file.display(), line, col));
+    let text = fs::read_to_string(cwd.join(file)).ok()
+      .and_then(|s| s.lines().nth((line as usize).checked_sub(1)?).map(|l| l.to_string()));
+    let gutter = " ".repeat(line.to_string().len());
+    if let Some(text) = text {
+      // Tabs are kept, so the caret lines up however wide they are.
+      let pad: String = text.chars().take((col as usize).saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+      log.push_str(&format!("{} |\n{} | {}\n{} | {}^\n", gutter, line, text, gutter, pad));
+    }
+    if !targets.is_empty() {
+      log.push_str(&format!("{} = target: {}\n", gutter, targets));
+    }
   }
+  println!("{}", log);
 }
@@ -1,9 +1,16 @@
-extern crate gcc;
+extern crate cc;
+
+use std::env;
 
 fn main() {
-  gcc::Config::new()
+  // The same C runtime as the Rust code linking this, on MSVC.
+  let crt_static = env::var("CARGO_CFG_TARGET_FEATURE")
+    .map(|f| f.split(',').any(|f| f == "crt-static"))
+    .unwrap_or(false);
+  cc::Build::new()
     .cpp(true)
     .file("src/tasksys.cpp")
     .define("ISPC_USE_PTHREADS", None)
-    .compile("librispcrt.a");
+    .static_crt(crt_static)
+    .compile("rispcrt");
 }
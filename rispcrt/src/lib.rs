@@ -38,3 +38,44 @@ macro_rules! ispc_module {
         }
     )
 }
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, RwLock};
+
+type InstrumentHook = Arc<dyn Fn(&str, &str, i32, u64) + Send + Sync>;
+
+static INSTRUMENT_HOOK: RwLock<Option<InstrumentHook>> = RwLock::new(None);
+
+/// Registers the closure called for every event emitted by ispc code built with
+/// `rispc::Config::instrument(true)`, replacing any previous one.
+///
+/// The closure receives the source file, a note describing the event, the
+/// source line, and the execution mask at that point. It may be called from
+/// several task threads at once, and may replace or clear itself. A panic in it
+/// is caught, since it can't unwind into ispc code, and the event is dropped.
+pub fn set_instrument_hook<F>(f: F)
+  where F: Fn(&str, &str, i32, u64) + Send + Sync + 'static {
+  *INSTRUMENT_HOOK.write().unwrap() = Some(Arc::new(f));
+}
+
+/// Removes the closure registered with `set_instrument_hook`. Events are
+/// dropped until a new one is registered.
+pub fn clear_instrument_hook() {
+  *INSTRUMENT_HOOK.write().unwrap() = None;
+}
+
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn ISPCInstrument(file: *const c_char, note: *const c_char,
+                                        line: i32, mask: u64) {
+  // Cloned out, so the hook isn't called with the lock held.
+  let hook = match *INSTRUMENT_HOOK.read().unwrap() {
+    Some(ref hook) => hook.clone(),
+    None           => return,
+  };
+  let file = CStr::from_ptr(file).to_string_lossy();
+  let note = CStr::from_ptr(note).to_string_lossy();
+  let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(&file, &note, line, mask)));
+}